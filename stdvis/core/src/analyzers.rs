@@ -0,0 +1,3 @@
+mod pinhole;
+
+pub use self::pinhole::{Intrinsics, PinholeAnalyzer, PinholeConfig};
@@ -0,0 +1,408 @@
+use anyhow::{bail, ensure, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    geometry::Point,
+    model::{ModelRef, TargetModel},
+    traits::ContourAnalyzer,
+    types::{CameraConfig, ContourGroup, VisionTarget},
+    uncertainty::{congruence, NoiseModel, Uncertainty},
+};
+
+/// The pinhole camera parameters of a `CameraConfig`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Intrinsics {
+    pub fx: f64,
+    pub fy: f64,
+    pub cx: f64,
+    pub cy: f64,
+    /// The distortion coefficients `k1, k2, p1, p2, k3`, with missing
+    /// coefficients set to zero.
+    pub distortion: [f64; 5],
+}
+
+impl Intrinsics {
+    pub fn from_config(config: &CameraConfig) -> Result<Self> {
+        let matrix = &config.intrinsic_matrix;
+
+        ensure!(
+            matrix.shape() == [3, 3],
+            "camera {} has no calibrated intrinsic matrix",
+            config.id
+        );
+
+        let mut distortion = [0.; 5];
+        for (coeff, value) in distortion.iter_mut().zip(&config.distortion_coeffs) {
+            *coeff = *value;
+        }
+
+        Ok(Self {
+            fx: matrix[[0, 0]],
+            fy: matrix[[1, 1]],
+            cx: matrix[[0, 2]],
+            cy: matrix[[1, 2]],
+            distortion,
+        })
+    }
+
+    /// Converts a pixel to a direction in the camera frame, removing lens
+    /// distortion. The direction's forward component is always 1.
+    pub fn direction(&self, (u, v): Point) -> [f64; 3] {
+        let [k1, k2, p1, p2, k3] = self.distortion;
+
+        let x0 = (u as f64 - self.cx) / self.fx;
+        let y0 = (v as f64 - self.cy) / self.fy;
+        let (mut x, mut y) = (x0, y0);
+
+        // Invert the distortion model iteratively, as OpenCV does.
+        for _ in 0..5 {
+            let r2 = x * x + y * y;
+            let inv_radial = 1. / (1. + ((k3 * r2 + k2) * r2 + k1) * r2);
+            let dx = 2. * p1 * x * y + p2 * (r2 + 2. * x * x);
+            let dy = p1 * (r2 + 2. * y * y) + 2. * p2 * x * y;
+
+            x = (x0 - dx) * inv_radial;
+            y = (y0 - dy) * inv_radial;
+        }
+
+        // Image `x` points right and `y` points down.
+        [1., -x, -y]
+    }
+}
+
+/// Parameters for a `PinholeAnalyzer`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PinholeConfig {
+    /// The model of the target, whose height above the floor is used to find
+    /// its distance.
+    pub model: ModelRef,
+}
+
+/// A `ContourAnalyzer` which finds a target's bearing from the centroid of
+/// its group, and its distance from the known heights of the target and the
+/// camera.
+///
+/// The yaw of the target's face is estimated from how foreshortened the group
+/// is compared to the model, and is only reported for groups with more than
+/// one contour, since the side which is closer cannot be told otherwise.
+/// Confidence is the mean solidity of the group's contours, scaled down when
+/// the number of contours differs from the number of pieces in the model.
+pub struct PinholeAnalyzer {
+    model: TargetModel,
+}
+
+impl PinholeAnalyzer {
+    pub fn new(config: PinholeConfig) -> Result<Self> {
+        Ok(Self {
+            model: config.model.resolve()?,
+        })
+    }
+
+    pub fn model(&self) -> &TargetModel {
+        &self.model
+    }
+
+    /// Estimates the uncertainty of the target this analyzer finds in
+    /// `group`, from the noise of the group's center.
+    ///
+    /// Distance comes from the elevation of the ray to the target, so its
+    /// error grows with the square of the distance over the height the ray
+    /// climbs.
+    pub fn uncertainty(&self, noise: &NoiseModel, group: &ContourGroup) -> Result<Uncertainty> {
+        let camera = group.camera;
+        let intrinsics = Intrinsics::from_config(camera)?;
+
+        let centroid = match group_centroid(group) {
+            Some(centroid) => centroid,
+            None => bail!("group {} has no contours", group.id),
+        };
+        let direction = intrinsics.direction(centroid);
+        let (x, y) = (-direction[1], -direction[2]);
+
+        let mount = camera.pose.transform().rotation;
+        let rise = mount[2][0] - mount[2][1] * x - mount[2][2] * y;
+        let climb = self.model.height - camera.pose.height;
+
+        ensure!(
+            rise.abs() > f64::EPSILON && climb / rise > 0.,
+            "target in group {} is not above or below the camera as its height implies",
+            group.id
+        );
+
+        // `theta` is `atan(-x)` and `dist` is `climb * hypot(1, x) / rise`.
+        let dist = climb * x.hypot(1.) / rise;
+        let jacobian = [
+            [-1. / (1. + x * x), 0.],
+            [
+                dist * (x / (1. + x * x) + mount[2][1] / rise),
+                dist * mount[2][2] / rise,
+            ],
+        ];
+
+        let center_noise = noise.center_noise(group)?;
+        let (sx, sy) = (center_noise / intrinsics.fx, center_noise / intrinsics.fy);
+
+        Ok(Uncertainty {
+            covariance: congruence(&jacobian, &[[sx * sx, 0.], [0., sy * sy]]),
+        })
+    }
+
+    /// Returns the yaw of the target's face from the group's shape.
+    fn estimate_beta(&self, group: &ContourGroup) -> f64 {
+        if group.contours.len() < 2 {
+            return 0.;
+        }
+
+        let (mut min, mut max) = ([f64::INFINITY; 2], [f64::NEG_INFINITY; 2]);
+        for point in self.model.points() {
+            for axis in 0..2 {
+                min[axis] = min[axis].min(point[axis]);
+                max[axis] = max[axis].max(point[axis]);
+            }
+        }
+        let model_aspect = (max[0] - min[0]) / (max[1] - min[1]);
+
+        let mut pieces = group
+            .contours
+            .iter()
+            .map(|contour| contour.bounding_box())
+            .collect::<Vec<_>>();
+        pieces.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap_or(std::cmp::Ordering::Equal));
+
+        let left = pieces[0];
+        let right = pieces[pieces.len() - 1];
+
+        let width = (right.x + right.width - left.x) as f64;
+        let height = pieces.iter().map(|bounds| bounds.height).fold(0., f32::max) as f64;
+
+        if height == 0. || !model_aspect.is_finite() {
+            return 0.;
+        }
+
+        let magnitude = (width / height / model_aspect).clamp(0., 1.).acos();
+
+        // The side of the target which is closer appears taller.
+        if left.height > right.height {
+            magnitude
+        } else {
+            -magnitude
+        }
+    }
+
+    fn confidence(&self, group: &ContourGroup) -> f32 {
+        let count = group.contours.len();
+        let expected = self.model.pieces.len();
+
+        if count == 0 {
+            return 0.;
+        }
+
+        let solidity = group
+            .contours
+            .iter()
+            .map(|contour| contour.solidity())
+            .sum::<f64>()
+            / count as f64;
+        let count_match = count.min(expected) as f64 / count.max(expected) as f64;
+
+        (solidity * count_match) as f32
+    }
+}
+
+impl ContourAnalyzer for PinholeAnalyzer {
+    fn analyze(&self, contours: &ContourGroup) -> Result<VisionTarget> {
+        let camera = contours.camera;
+        let intrinsics = Intrinsics::from_config(camera)?;
+
+        let centroid = match group_centroid(contours) {
+            Some(centroid) => centroid,
+            None => bail!("group {} has no contours", contours.id),
+        };
+        let direction = intrinsics.direction(centroid);
+
+        // Scale the ray so that it reaches the target's height in the robot
+        // frame.
+        let mount = camera.pose.transform();
+        let rise = mount.rotate(direction)[2];
+        let climb = self.model.height - camera.pose.height;
+
+        ensure!(
+            rise.abs() > f64::EPSILON && climb / rise > 0.,
+            "target in group {} is not above or below the camera as its height implies",
+            contours.id
+        );
+
+        let scale = climb / rise;
+        let [x, y, z] = direction.map(|component| component * scale);
+
+        Ok(VisionTarget {
+            id: contours.id,
+            beta: self.estimate_beta(contours),
+            theta: y.atan2(x),
+            dist: x.hypot(y),
+            height: z,
+            confidence: self.confidence(contours),
+            ..VisionTarget::default()
+        })
+    }
+}
+
+/// Returns the area-weighted centroid of every contour in a group.
+fn group_centroid(group: &ContourGroup) -> Option<Point> {
+    let (mut sum_x, mut sum_y, mut total) = (0., 0., 0.);
+
+    for contour in &group.contours {
+        let area = contour.area().max(f64::EPSILON);
+
+        if let Some((x, y)) = contour.centroid() {
+            sum_x += x as f64 * area;
+            sum_y += y as f64 * area;
+            total += area;
+        }
+    }
+
+    if total == 0. {
+        return None;
+    }
+
+    Some(((sum_x / total) as f32, (sum_y / total) as f32))
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{arr1, arr2};
+
+    use super::*;
+    use crate::{
+        model::{Symmetry, TargetPiece},
+        types::{Contour, Pose},
+    };
+
+    #[test]
+    fn test_pinhole_distance() {
+        let camera = CameraConfig {
+            resolution: (640, 480),
+            pose: Pose {
+                height: 0.5,
+                pitch: 0.3,
+                ..Pose::default()
+            },
+            intrinsic_matrix: arr2(&[[500., 0., 320.], [0., 500., 240.], [0., 0., 1.]]),
+            distortion_coeffs: arr1(&[0., 0., 0., 0., 0.]),
+            ..CameraConfig::default()
+        };
+
+        let model = TargetModel {
+            name: "square".to_owned(),
+            height: 2.5,
+            pieces: vec![TargetPiece {
+                corners: vec![[-0.1, 0.1, 0.], [0.1, 0.1, 0.], [0.1, -0.1, 0.]],
+                tape_size: None,
+            }],
+            symmetry: Symmetry::None,
+        };
+        let analyzer = PinholeAnalyzer::new(PinholeConfig {
+            model: ModelRef::Inline(model),
+        })
+        .unwrap();
+
+        // Project a target 4 meters ahead, 2 meters above the camera and
+        // slightly to the left.
+        let robot_point = [4., 0.4, 2.5];
+        let camera_point = camera.pose.transform().inverse().apply(robot_point);
+        let u = 320. - 500. * camera_point[1] / camera_point[0];
+        let v = 240. - 500. * camera_point[2] / camera_point[0];
+        let (u, v) = (u as f32, v as f32);
+
+        let group = ContourGroup {
+            id: 3,
+            camera: &camera,
+            contours: vec![Contour::new(vec![
+                (u - 5., v - 5.),
+                (u + 5., v - 5.),
+                (u + 5., v + 5.),
+                (u - 5., v + 5.),
+            ])],
+            fiducial: None,
+        };
+
+        let target = analyzer.analyze(&group).unwrap().to_robot_frame(&camera);
+
+        assert_eq!(target.id, 3);
+        assert!((target.dist - 4f64.hypot(0.4)).abs() < 1e-3);
+        assert!((target.theta - 0.4f64.atan2(4.)).abs() < 1e-4);
+        assert!((target.height - 2.5).abs() < 1e-6);
+        assert!((target.confidence - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_pinhole_uncertainty() {
+        let camera = CameraConfig {
+            pose: Pose {
+                height: 0.5,
+                pitch: 0.3,
+                ..Pose::default()
+            },
+            intrinsic_matrix: arr2(&[[500., 0., 320.], [0., 500., 240.], [0., 0., 1.]]),
+            distortion_coeffs: arr1(&[0., 0., 0., 0., 0.]),
+            ..CameraConfig::default()
+        };
+        let model = TargetModel {
+            name: "square".to_owned(),
+            height: 2.5,
+            pieces: vec![TargetPiece {
+                corners: vec![[-0.1, 0.1, 0.], [0.1, 0.1, 0.], [0.1, -0.1, 0.]],
+                tape_size: None,
+            }],
+            symmetry: Symmetry::None,
+        };
+        let analyzer = PinholeAnalyzer::new(PinholeConfig {
+            model: ModelRef::Inline(model),
+        })
+        .unwrap();
+
+        let group_at = |(u, v): (f32, f32)| ContourGroup {
+            id: 0,
+            camera: &camera,
+            contours: vec![Contour::new(vec![
+                (u - 5., v - 5.),
+                (u + 5., v - 5.),
+                (u + 5., v + 5.),
+                (u - 5., v + 5.),
+            ])],
+            fiducial: None,
+        };
+        let measure = |center| {
+            let target = analyzer.analyze(&group_at(center)).unwrap();
+            [target.theta, target.dist]
+        };
+
+        let center = (420., 200.);
+        let noise = NoiseModel::default();
+        let uncertainty = analyzer.uncertainty(&noise, &group_at(center)).unwrap();
+
+        // Differentiate the measurement by the center's position.
+        let step = 0.01;
+        let base = measure(center);
+        let du = measure((center.0 + step, center.1));
+        let dv = measure((center.0, center.1 + step));
+        let jacobian = [0, 1].map(|row| {
+            [
+                (du[row] - base[row]) / step as f64,
+                (dv[row] - base[row]) / step as f64,
+            ]
+        });
+
+        let pixel = noise.center_noise(&group_at(center)).unwrap().powi(2);
+        let expected = congruence(&jacobian, &[[pixel, 0.], [0., pixel]]);
+
+        for (row, expected) in uncertainty.covariance.iter().zip(&expected) {
+            for (value, expected) in row.iter().zip(expected) {
+                assert!(
+                    (value - expected).abs() <= 1e-2 * expected.abs().max(1e-9),
+                    "{value} != {expected}"
+                );
+            }
+        }
+    }
+}
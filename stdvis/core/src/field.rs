@@ -0,0 +1,147 @@
+//! Layouts of the landmarks placed around a field.
+//!
+//! The field frame has its origin on the floor, with `x` and `y` along the
+//! floor and `z` up. Each landmark is placed by the position of its model
+//! frame's origin and the direction its face points. A landmark's yaw, pitch
+//! and roll follow the same conventions as a [`Pose`](crate::types::Pose),
+//! applied to a frame whose forward axis points out of the landmark's face.
+
+use std::{fs, path::Path};
+
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    model::ModelRef,
+    transform::{Transform, Vector3},
+};
+
+/// The change of basis from a model frame (`x` right, `y` up, `z` out of the
+/// face) to a frame whose forward axis points out of the face.
+const MODEL_TO_FACING: [[f64; 3]; 3] = [[0., 0., 1.], [1., 0., 0.], [0., 1., 0.]];
+
+/// A single landmark on the field.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Landmark {
+    /// The id of the landmark's marker.
+    pub id: u16,
+    /// The position of the landmark's model origin on the field, in meters.
+    pub position: Vector3,
+    /// The direction the landmark's face points, in radians.
+    pub yaw: f64,
+    #[serde(default)]
+    pub pitch: f64,
+    #[serde(default)]
+    pub roll: f64,
+}
+
+impl Landmark {
+    /// Returns the transform from the landmark's model frame into the field
+    /// frame.
+    pub fn model_to_field(&self) -> Transform {
+        let facing = Transform::from_euler(self.yaw, self.pitch, self.roll, self.position);
+
+        facing.then(&Transform {
+            rotation: MODEL_TO_FACING,
+            translation: [0., 0., 0.],
+        })
+    }
+}
+
+/// The landmarks on a field, which all share one target model.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FieldMap {
+    pub name: String,
+    /// The model of every landmark, such as a fiducial tag of a given size.
+    pub model: ModelRef,
+    pub landmarks: Vec<Landmark>,
+}
+
+impl FieldMap {
+    /// Parses a field map from its JSON representation.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let map: Self = serde_json::from_str(json).context("parsing field map")?;
+        map.validate()?;
+
+        Ok(map)
+    }
+
+    /// Loads a field map from a JSON file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json =
+            fs::read_to_string(path).with_context(|| format!("reading field map file {path:?}"))?;
+
+        Self::from_json(&json).with_context(|| format!("loading field map {path:?}"))
+    }
+
+    /// Checks that no two landmarks share an id.
+    pub fn validate(&self) -> Result<()> {
+        let mut ids = self
+            .landmarks
+            .iter()
+            .map(|landmark| landmark.id)
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+
+        for pair in ids.windows(2) {
+            ensure!(
+                pair[0] != pair[1],
+                "field map {} has more than one landmark with id {}",
+                self.name,
+                pair[0]
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns the landmark with the given id, if there is one.
+    pub fn landmark(&self, id: u16) -> Option<&Landmark> {
+        self.landmarks.iter().find(|landmark| landmark.id == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+
+    use super::*;
+
+    #[test]
+    fn test_field_map() {
+        let map = FieldMap::from_json(
+            r#"{
+                "name": "test",
+                "model": "apriltag_36h11_6in",
+                "landmarks": [
+                    { "id": 1, "position": [5.0, 0.0, 1.0], "yaw": 3.141592653589793 },
+                    { "id": 2, "position": [0.0, 3.0, 1.0], "yaw": -1.5707963267948966 }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(map.model, ModelRef::Name("apriltag_36h11_6in".to_owned()));
+        assert!(map.landmark(3).is_none());
+
+        // Seen from the origin, the right of the tag is towards -y.
+        let landmark = map.landmark(1).unwrap();
+        assert!((landmark.yaw - PI).abs() < 1e-12);
+
+        let [x, y, z] = landmark.model_to_field().apply([0.1, 0.2, 0.]);
+        assert!((x - 5.).abs() < 1e-9);
+        assert!((y + 0.1).abs() < 1e-9);
+        assert!((z - 1.2).abs() < 1e-9);
+
+        let duplicate = r#"{
+            "name": "duplicate",
+            "model": "apriltag_36h11_6in",
+            "landmarks": [
+                { "id": 1, "position": [0.0, 0.0, 0.0], "yaw": 0.0 },
+                { "id": 1, "position": [1.0, 0.0, 0.0], "yaw": 0.0 }
+            ]
+        }"#;
+        assert!(FieldMap::from_json(duplicate).is_err());
+    }
+}
@@ -0,0 +1,250 @@
+//! A configurable chain of rules which drops unwanted contours between
+//! extraction and analysis.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{CameraConfig, Contour, ContourGroup};
+
+/// An inclusive range of accepted values. Either bound may be omitted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Range {
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+}
+
+impl Range {
+    pub fn new(min: Option<f64>, max: Option<f64>) -> Self {
+        Self { min, max }
+    }
+
+    pub fn contains(&self, value: f64) -> bool {
+        self.min.iter().all(|&min| value >= min) && self.max.iter().all(|&max| value <= max)
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let min = self.min.unwrap_or(f64::NEG_INFINITY);
+        let max = self.max.unwrap_or(f64::INFINITY);
+
+        write!(f, "[{min}, {max}]")
+    }
+}
+
+/// A single rule in a `FilterChain`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FilterRule {
+    /// The enclosed area, in pixels.
+    Area(Range),
+    /// The width of the bounding box divided by its height.
+    AspectRatio(Range),
+    /// The area divided by the area of the convex hull.
+    Solidity(Range),
+    /// The area divided by the area of the bounding box.
+    Extent(Range),
+    /// The number of vertices after polygon approximation, where `epsilon`
+    /// is the approximation tolerance as a fraction of the perimeter.
+    Vertices { epsilon: f64, range: Range },
+    /// The angle of the major axis from the image `x` axis, in radians.
+    Orientation(Range),
+    /// The position of the centroid, as fractions of the frame's width and
+    /// height.
+    Position { x: Range, y: Range },
+}
+
+impl FilterRule {
+    /// Checks a contour against this rule, returning the measured value if
+    /// it was rejected.
+    pub fn check(&self, contour: &Contour, camera: &CameraConfig) -> Option<f64> {
+        let (range, value) = match self {
+            FilterRule::Area(range) => (range, contour.area()),
+            FilterRule::AspectRatio(range) => (range, contour.aspect_ratio()),
+            FilterRule::Solidity(range) => (range, contour.solidity()),
+            FilterRule::Extent(range) => (range, contour.extent()),
+            FilterRule::Vertices { epsilon, range } => {
+                let epsilon = (epsilon * contour.perimeter()) as f32;
+                (range, contour.approx_poly(epsilon).points.len() as f64)
+            }
+            FilterRule::Orientation(range) => (range, contour.moments().orientation()),
+            FilterRule::Position { x, y } => {
+                let (cx, cy) = contour.centroid()?;
+                let (width, height) = camera.resolution;
+
+                let cx = cx as f64 / width as f64;
+                if !x.contains(cx) {
+                    return Some(cx);
+                }
+
+                (y, cy as f64 / height as f64)
+            }
+        };
+
+        if range.contains(value) {
+            None
+        } else {
+            Some(value)
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            FilterRule::Area(_) => "area",
+            FilterRule::AspectRatio(_) => "aspect ratio",
+            FilterRule::Solidity(_) => "solidity",
+            FilterRule::Extent(_) => "extent",
+            FilterRule::Vertices { .. } => "vertices",
+            FilterRule::Orientation(_) => "orientation",
+            FilterRule::Position { .. } => "position",
+        }
+    }
+}
+
+/// The reason a contour was dropped by a `FilterChain`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rejection {
+    /// The id of the group the contour belonged to.
+    pub group: u16,
+    /// The index of the contour within its group, before filtering.
+    pub contour: usize,
+    /// The index of the rule which rejected the contour.
+    pub rule: usize,
+    /// The value measured by that rule.
+    pub value: f64,
+}
+
+/// An ordered list of rules that every contour must pass.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FilterChain {
+    pub rules: Vec<FilterRule>,
+}
+
+impl FilterChain {
+    pub fn new(rules: Vec<FilterRule>) -> Self {
+        Self { rules }
+    }
+
+    /// Returns the index of the first rule that rejects `contour`, along with
+    /// the value it measured.
+    pub fn check(&self, contour: &Contour, camera: &CameraConfig) -> Option<(usize, f64)> {
+        self.rules
+            .iter()
+            .enumerate()
+            .find_map(|(idx, rule)| rule.check(contour, camera).map(|value| (idx, value)))
+    }
+
+    /// Removes every contour that fails a rule, dropping groups which are
+    /// left empty, and returns the reason for each removal.
+    pub fn apply(&self, groups: &mut Vec<ContourGroup>) -> Vec<Rejection> {
+        let mut rejections = Vec::new();
+
+        for group in groups.iter_mut() {
+            let (group_id, camera) = (group.id, group.camera);
+            let mut idx = 0;
+
+            group.contours.retain(|contour| {
+                let result = self.check(contour, camera);
+
+                if let Some((rule, value)) = result {
+                    rejections.push(Rejection {
+                        group: group_id,
+                        contour: idx,
+                        rule,
+                        value,
+                    });
+                }
+
+                idx += 1;
+                result.is_none()
+            });
+        }
+
+        groups.retain(|group| !group.contours.is_empty());
+
+        rejections
+    }
+
+    /// Describes a rejection in terms of the rule which caused it.
+    pub fn describe(&self, rejection: &Rejection) -> String {
+        let rule = &self.rules[rejection.rule];
+        let range = match rule {
+            FilterRule::Area(range)
+            | FilterRule::AspectRatio(range)
+            | FilterRule::Solidity(range)
+            | FilterRule::Extent(range)
+            | FilterRule::Orientation(range)
+            | FilterRule::Vertices { range, .. } => range.to_string(),
+            FilterRule::Position { x, y } => format!("x: {x}, y: {y}"),
+        };
+
+        format!(
+            "contour {} of group {} rejected by {} rule {}: {} not in {range}",
+            rejection.contour,
+            rejection.group,
+            rule.name(),
+            rejection.rule,
+            rejection.value,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_chain() {
+        let camera = CameraConfig {
+            resolution: (100, 100),
+            ..CameraConfig::default()
+        };
+
+        let small = Contour::new(vec![(0., 0.), (2., 0.), (2., 2.), (0., 2.)]);
+        let tall = Contour::new(vec![(10., 10.), (14., 10.), (14., 30.), (10., 30.)]);
+        let wide = Contour::new(vec![(50., 50.), (90., 50.), (90., 60.), (50., 60.)]);
+
+        let mut groups = vec![
+            ContourGroup {
+                id: 0,
+                camera: &camera,
+                contours: vec![small, tall.clone()],
+                fiducial: None,
+            },
+            ContourGroup {
+                id: 1,
+                camera: &camera,
+                contours: vec![wide],
+                fiducial: None,
+            },
+        ];
+
+        let chain = FilterChain::new(vec![
+            FilterRule::Area(Range::new(Some(10.), None)),
+            FilterRule::AspectRatio(Range::new(None, Some(1.))),
+        ]);
+        let rejections = chain.apply(&mut groups);
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].contours, vec![tall]);
+        assert_eq!(
+            rejections,
+            vec![
+                Rejection {
+                    group: 0,
+                    contour: 0,
+                    rule: 0,
+                    value: 4.,
+                },
+                Rejection {
+                    group: 1,
+                    contour: 0,
+                    rule: 1,
+                    value: 4.,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,685 @@
+//! Fusion of targets seen by several cameras into single robot-relative
+//! estimates.
+//!
+//! Measurements are moved into the robot frame at the latest capture time
+//! among them, using the robot's odometry history if one is given, or by
+//! widening the uncertainty of older measurements otherwise. Measurements
+//! from different cameras which agree within their uncertainty are then
+//! combined, weighting each by the inverse of its position covariance.
+//!
+//! Robot poses localized by several cameras are fused in the same way, each
+//! weighted by the inverse of its variance.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    odometry::{FieldPose, OdometryHistory},
+    transform::normalize_angle,
+    types::{CameraConfig, VisionTarget},
+    uncertainty::Uncertainty,
+};
+
+type Matrix2 = [[f64; 2]; 2];
+
+/// A target measured by one camera.
+#[derive(Clone, Debug)]
+pub struct Measurement<'src> {
+    pub camera: &'src CameraConfig,
+    /// The target, measured relative to the camera.
+    pub target: VisionTarget,
+    pub uncertainty: Uncertainty,
+}
+
+/// Parameters for a `Fuser`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FusionConfig {
+    /// The largest difference between capture times, in microseconds, for
+    /// measurements to be fused. Older measurements are dropped.
+    #[serde(default = "FusionConfig::default_max_time_skew")]
+    pub max_time_skew: u64,
+    /// The fastest the robot is expected to move relative to a target, in
+    /// meters per second, used to widen the uncertainty of older measurements
+    /// when no odometry is available.
+    #[serde(default = "FusionConfig::default_max_speed")]
+    pub max_speed: f64,
+    /// The largest squared Mahalanobis distance between two measurements for
+    /// them to be considered the same target.
+    #[serde(default = "FusionConfig::default_gate")]
+    pub gate: f64,
+    /// Whether only measurements with the same target id may be fused, for
+    /// targets whose ids identify them.
+    #[serde(default)]
+    pub match_ids: bool,
+    /// The fastest the robot is expected to turn, in radians per second, used
+    /// to widen the heading uncertainty of older poses when no odometry is
+    /// available.
+    #[serde(default = "FusionConfig::default_max_turn_rate")]
+    pub max_turn_rate: f64,
+}
+
+impl FusionConfig {
+    fn default_max_time_skew() -> u64 {
+        50_000
+    }
+
+    fn default_max_speed() -> f64 {
+        4.
+    }
+
+    fn default_gate() -> f64 {
+        9.21
+    }
+
+    fn default_max_turn_rate() -> f64 {
+        2. * std::f64::consts::PI
+    }
+}
+
+impl Default for FusionConfig {
+    fn default() -> Self {
+        Self {
+            max_time_skew: Self::default_max_time_skew(),
+            max_speed: Self::default_max_speed(),
+            gate: Self::default_gate(),
+            match_ids: false,
+            max_turn_rate: Self::default_max_turn_rate(),
+        }
+    }
+}
+
+/// A target estimated from the measurements of one or more cameras.
+#[derive(Clone, Debug)]
+pub struct FusedTarget {
+    /// The target relative to the robot, at the latest capture time of the
+    /// fused measurements.
+    pub target: VisionTarget,
+    pub uncertainty: Uncertainty,
+    /// The ids of the cameras whose measurements were fused.
+    pub cameras: Vec<u8>,
+}
+
+/// A robot pose on the field estimated by one camera.
+#[derive(Clone, Debug)]
+pub struct PoseMeasurement {
+    pub camera: u8,
+    pub pose: FieldPose,
+    /// The standard deviation of the pose's position, in meters.
+    pub position_std_dev: f64,
+    /// The standard deviation of the pose's heading, in radians.
+    pub heading_std_dev: f64,
+    /// The time the frame was captured, in microseconds since the Unix epoch.
+    pub capture_time: u64,
+}
+
+/// A robot pose estimated from the measurements of one or more cameras.
+#[derive(Clone, Debug)]
+pub struct FusedPose {
+    /// The pose at the latest capture time of the fused measurements.
+    pub pose: FieldPose,
+    pub position_std_dev: f64,
+    pub heading_std_dev: f64,
+    pub capture_time: u64,
+    /// The ids of the cameras whose measurements were fused.
+    pub cameras: Vec<u8>,
+}
+
+/// A measurement moved into the robot frame at the reference time.
+struct Aligned {
+    camera: u8,
+    target: VisionTarget,
+    position: [f64; 2],
+    covariance: Matrix2,
+    facing: f64,
+}
+
+/// Measurements believed to be of the same target, with their combined
+/// information.
+#[derive(Default)]
+struct Cluster {
+    members: Vec<Aligned>,
+    information: Matrix2,
+    weighted: [f64; 2],
+}
+
+impl Cluster {
+    /// Returns the combined position and its covariance.
+    fn estimate(&self) -> Option<([f64; 2], Matrix2)> {
+        let covariance = invert(&self.information)?;
+        let w = &self.weighted;
+        let position = [
+            covariance[0][0] * w[0] + covariance[0][1] * w[1],
+            covariance[1][0] * w[0] + covariance[1][1] * w[1],
+        ];
+
+        Some((position, covariance))
+    }
+
+    fn add(&mut self, member: Aligned) -> bool {
+        let information = match invert(&member.covariance) {
+            Some(information) => information,
+            None => return false,
+        };
+
+        let p = member.position;
+        let rows = self
+            .information
+            .iter_mut()
+            .zip(&mut self.weighted)
+            .zip(&information);
+        for ((sum, weighted), row) in rows {
+            for (sum, value) in sum.iter_mut().zip(row) {
+                *sum += value;
+            }
+            *weighted += row[0] * p[0] + row[1] * p[1];
+        }
+
+        self.members.push(member);
+        true
+    }
+}
+
+/// Fuses the targets seen by several cameras.
+pub struct Fuser {
+    config: FusionConfig,
+}
+
+impl Fuser {
+    pub fn new(config: FusionConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &FusionConfig {
+        &self.config
+    }
+
+    /// Fuses the measurements from one frame of each camera.
+    ///
+    /// Each target's capture time must be set, and `odometry`, if given, must
+    /// use the same clock.
+    pub fn fuse(
+        &self,
+        measurements: &[Measurement],
+        odometry: Option<&OdometryHistory>,
+    ) -> Vec<FusedTarget> {
+        let reference = match measurements
+            .iter()
+            .map(|measurement| measurement.target.capture_time)
+            .max()
+        {
+            Some(reference) => reference,
+            None => return Vec::new(),
+        };
+
+        let mut aligned = measurements
+            .iter()
+            .filter(|measurement| {
+                reference - measurement.target.capture_time <= self.config.max_time_skew
+            })
+            .map(|measurement| self.align(measurement, reference, odometry))
+            .collect::<Vec<_>>();
+
+        // Start clusters from the most precise measurements.
+        aligned.sort_by(|a, b| {
+            trace(&a.covariance)
+                .partial_cmp(&trace(&b.covariance))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let mut clusters: Vec<Cluster> = Vec::new();
+
+        for member in aligned {
+            let mut best: Option<(usize, f64)> = None;
+
+            for (idx, cluster) in clusters.iter().enumerate() {
+                let first = &cluster.members[0];
+                let same_camera = cluster
+                    .members
+                    .iter()
+                    .any(|other| other.camera == member.camera);
+
+                if same_camera || (self.config.match_ids && first.target.id != member.target.id) {
+                    continue;
+                }
+
+                let distance = match cluster.estimate() {
+                    Some((position, covariance)) => distance(
+                        member.position,
+                        position,
+                        &add(&member.covariance, &covariance),
+                    ),
+                    None => continue,
+                };
+
+                if distance <= self.config.gate && best.iter().all(|&(_, min)| distance < min) {
+                    best = Some((idx, distance));
+                }
+            }
+
+            match best {
+                Some((idx, _)) => {
+                    clusters[idx].add(member);
+                }
+                None => {
+                    let mut cluster = Cluster::default();
+                    if cluster.add(member) {
+                        clusters.push(cluster);
+                    }
+                }
+            }
+        }
+
+        clusters
+            .into_iter()
+            .filter_map(|cluster| combine(cluster, reference))
+            .collect()
+    }
+
+    /// Fuses the robot poses localized by each camera in one frame.
+    ///
+    /// Poses are moved to the latest capture time among them like targets
+    /// are. Starting from the most precise pose, each pose within the gate of
+    /// the estimate so far is combined into it, so a camera which localizes
+    /// far from the others is left out rather than pulling the estimate away.
+    pub fn fuse_poses(
+        &self,
+        measurements: &[PoseMeasurement],
+        odometry: Option<&OdometryHistory>,
+    ) -> Option<FusedPose> {
+        let reference = measurements
+            .iter()
+            .map(|measurement| measurement.capture_time)
+            .max()?;
+
+        let mut aligned = measurements
+            .iter()
+            .filter(|measurement| reference - measurement.capture_time <= self.config.max_time_skew)
+            .map(|measurement| self.align_pose(measurement, reference, odometry))
+            .filter(|(_, position_variance, heading_variance)| {
+                *position_variance > 0. && *heading_variance > 0.
+            })
+            .collect::<Vec<_>>();
+
+        aligned.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mut cameras = Vec::new();
+        let mut position_information = 0.;
+        let mut weighted = [0., 0.];
+        let mut heading_information = 0.;
+        let (mut sin, mut cos) = (0., 0.);
+
+        for (measurement, position_variance, heading_variance) in aligned {
+            let pose = &measurement.pose;
+
+            if position_information > 0. {
+                let estimate = [
+                    weighted[0] / position_information,
+                    weighted[1] / position_information,
+                ];
+                let (dx, dy) = (pose.x - estimate[0], pose.y - estimate[1]);
+                let variance = position_variance + 1. / position_information;
+
+                if (dx * dx + dy * dy) / variance > self.config.gate {
+                    continue;
+                }
+            }
+
+            position_information += 1. / position_variance;
+            weighted[0] += pose.x / position_variance;
+            weighted[1] += pose.y / position_variance;
+
+            heading_information += 1. / heading_variance;
+            sin += pose.heading.sin() / heading_variance;
+            cos += pose.heading.cos() / heading_variance;
+
+            cameras.push(measurement.camera);
+        }
+
+        if cameras.is_empty() {
+            return None;
+        }
+
+        Some(FusedPose {
+            pose: FieldPose::new(
+                weighted[0] / position_information,
+                weighted[1] / position_information,
+                f64::atan2(sin, cos),
+            ),
+            position_std_dev: position_information.recip().sqrt(),
+            heading_std_dev: heading_information.recip().sqrt(),
+            capture_time: reference,
+            cameras,
+        })
+    }
+
+    /// Moves a pose measurement to the reference time, returning it with its
+    /// position and heading variances.
+    fn align_pose(
+        &self,
+        measurement: &PoseMeasurement,
+        reference: u64,
+        odometry: Option<&OdometryHistory>,
+    ) -> (PoseMeasurement, f64, f64) {
+        let mut aligned = measurement.clone();
+        let mut position_variance = measurement.position_std_dev.powi(2);
+        let mut heading_variance = measurement.heading_std_dev.powi(2);
+
+        let poses = odometry.and_then(|history| {
+            Some((
+                history.at(measurement.capture_time)?,
+                history.at(reference)?,
+            ))
+        });
+
+        match poses {
+            Some((then, now)) => {
+                // Apply the motion since capture, in the robot's own frame.
+                let [x, y, _] = then.from_field([now.x, now.y, 0.]);
+                let [x, y, _] = measurement.pose.to_field([x, y, 0.]);
+                let heading = measurement.pose.heading + (now.heading - then.heading);
+
+                aligned.pose = FieldPose::new(x, y, normalize_angle(heading));
+            }
+            None => {
+                let skew = (reference - measurement.capture_time) as f64 / 1e6;
+                position_variance += (skew * self.config.max_speed).powi(2);
+                heading_variance += (skew * self.config.max_turn_rate).powi(2);
+            }
+        }
+
+        aligned.capture_time = reference;
+
+        (aligned, position_variance, heading_variance)
+    }
+
+    /// Moves a measurement into the robot frame at the reference time.
+    fn align(
+        &self,
+        measurement: &Measurement,
+        reference: u64,
+        odometry: Option<&OdometryHistory>,
+    ) -> Aligned {
+        let camera = measurement.camera;
+        let target = measurement.target.to_robot_frame(camera);
+        let uncertainty = measurement
+            .uncertainty
+            .to_robot_frame(&measurement.target, camera);
+
+        let mut covariance = uncertainty.position_covariance(&target);
+        let mut facing = target.theta + target.beta;
+        let mut point = target.position();
+
+        let poses = odometry
+            .and_then(|history| Some((history.at(target.capture_time)?, history.at(reference)?)));
+
+        match poses {
+            Some((then, now)) => {
+                point = now.from_field(then.to_field(point));
+
+                let turn = then.heading - now.heading;
+                let (sin, cos) = turn.sin_cos();
+                covariance = congruence(&[[cos, -sin], [sin, cos]], &covariance);
+                facing += turn;
+            }
+            None => {
+                let skew = (reference - target.capture_time) as f64 / 1e6;
+                let drift = (skew * self.config.max_speed).powi(2);
+                covariance[0][0] += drift;
+                covariance[1][1] += drift;
+            }
+        }
+
+        Aligned {
+            camera: camera.id,
+            target,
+            position: [point[0], point[1]],
+            covariance,
+            facing,
+        }
+    }
+}
+
+/// Builds the fused target of a cluster.
+fn combine(cluster: Cluster, reference: u64) -> Option<FusedTarget> {
+    let ([x, y], covariance) = cluster.estimate()?;
+    let members = &cluster.members;
+    let best = &members[0].target;
+
+    let weights = members
+        .iter()
+        .map(|member| 1. / trace(&member.covariance).max(f64::EPSILON))
+        .collect::<Vec<_>>();
+    let total = weights.iter().sum::<f64>();
+
+    let height = members
+        .iter()
+        .zip(&weights)
+        .map(|(member, weight)| member.target.height * weight)
+        .sum::<f64>()
+        / total;
+
+    let (sin, cos) = members
+        .iter()
+        .zip(&weights)
+        .fold((0., 0.), |(sin, cos), (member, weight)| {
+            (
+                sin + member.facing.sin() * weight,
+                cos + member.facing.cos() * weight,
+            )
+        });
+    let facing = sin.atan2(cos);
+
+    let theta = y.atan2(x);
+    let target = VisionTarget {
+        theta,
+        dist: x.hypot(y),
+        beta: normalize_angle(facing - theta),
+        height,
+        confidence: members
+            .iter()
+            .map(|member| member.target.confidence)
+            .fold(0., f32::max),
+        capture_time: reference,
+        latency: members
+            .iter()
+            .map(|member| member.target.latency)
+            .max()
+            .unwrap_or_default(),
+        ..best.clone()
+    };
+
+    Some(FusedTarget {
+        uncertainty: Uncertainty::from_position_covariance(&target, &covariance),
+        target,
+        cameras: members.iter().map(|member| member.camera).collect(),
+    })
+}
+
+fn trace(m: &Matrix2) -> f64 {
+    m[0][0] + m[1][1]
+}
+
+fn add(a: &Matrix2, b: &Matrix2) -> Matrix2 {
+    [
+        [a[0][0] + b[0][0], a[0][1] + b[0][1]],
+        [a[1][0] + b[1][0], a[1][1] + b[1][1]],
+    ]
+}
+
+fn invert(m: &Matrix2) -> Option<Matrix2> {
+    let det = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+
+    if det.abs() < f64::EPSILON * f64::EPSILON {
+        return None;
+    }
+
+    Some([
+        [m[1][1] / det, -m[0][1] / det],
+        [-m[1][0] / det, m[0][0] / det],
+    ])
+}
+
+/// Returns `a * m * a^T`.
+fn congruence(a: &Matrix2, m: &Matrix2) -> Matrix2 {
+    let mut out = [[0.; 2]; 2];
+
+    for (row, out) in out.iter_mut().enumerate() {
+        for (col, out) in out.iter_mut().enumerate() {
+            *out = (0..2)
+                .flat_map(|i| (0..2).map(move |j| (i, j)))
+                .map(|(i, j)| a[row][i] * m[i][j] * a[col][j])
+                .sum();
+        }
+    }
+
+    out
+}
+
+/// Returns the squared Mahalanobis distance between two positions.
+fn distance(a: [f64; 2], b: [f64; 2], covariance: &Matrix2) -> f64 {
+    let inverse = match invert(covariance) {
+        Some(inverse) => inverse,
+        None => return f64::INFINITY,
+    };
+    let d = [a[0] - b[0], a[1] - b[1]];
+
+    (0..2)
+        .map(|row| {
+            (0..2)
+                .map(|col| d[row] * inverse[row][col] * d[col])
+                .sum::<f64>()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use super::*;
+    use crate::{odometry::FieldPose, types::Pose};
+
+    #[test]
+    fn test_fuse_cameras() {
+        let front = CameraConfig {
+            id: 0,
+            ..CameraConfig::default()
+        };
+        let side = CameraConfig {
+            id: 1,
+            pose: Pose {
+                yaw: FRAC_PI_2,
+                ..Pose::default()
+            },
+            ..CameraConfig::default()
+        };
+
+        // A target at (3, 0) in the robot frame, seen slightly differently by
+        // each camera, and a second target seen only by the side camera.
+        let measurements = [
+            Measurement {
+                camera: &front,
+                target: VisionTarget {
+                    dist: 3.1,
+                    capture_time: 1_000_000,
+                    ..VisionTarget::default()
+                },
+                uncertainty: Uncertainty::from_std_devs(0.01, 0.1),
+            },
+            Measurement {
+                camera: &side,
+                target: VisionTarget {
+                    theta: -FRAC_PI_2,
+                    dist: 2.9,
+                    capture_time: 1_010_000,
+                    ..VisionTarget::default()
+                },
+                uncertainty: Uncertainty::from_std_devs(0.01, 0.1),
+            },
+            Measurement {
+                camera: &side,
+                target: VisionTarget {
+                    dist: 2.,
+                    capture_time: 1_010_000,
+                    ..VisionTarget::default()
+                },
+                uncertainty: Uncertainty::from_std_devs(0.01, 0.1),
+            },
+        ];
+
+        let fuser = Fuser::new(FusionConfig::default());
+        let mut fused = fuser.fuse(&measurements, None);
+        fused.sort_by_key(|fused| fused.cameras.len());
+
+        assert_eq!(fused.len(), 2);
+        assert_eq!(fused[1].cameras.len(), 2);
+        assert!(fused[1].target.dist > 2.9 && fused[1].target.dist < 3.1);
+        assert!(fused[1].uncertainty.dist_std_dev() < 0.1);
+        assert_eq!(fused[1].target.capture_time, 1_010_000);
+
+        // With odometry, the older measurement is moved by the robot's motion
+        // since it was captured.
+        let mut odometry = OdometryHistory::new(10);
+        odometry.record(1_000_000, FieldPose::new(0., 0., 0.));
+        odometry.record(1_010_000, FieldPose::new(0.2, 0., 0.));
+
+        let fused = fuser.fuse(&measurements[..1], Some(&odometry));
+        assert!((fused[0].target.dist - 3.1).abs() < 1e-9);
+
+        let moved = fuser.align(&measurements[0], 1_010_000, Some(&odometry));
+        assert!((moved.position[0] - 2.9).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_fuse_poses() {
+        let measurement = |camera, x, heading, std_dev| PoseMeasurement {
+            camera,
+            pose: FieldPose::new(x, 2., heading),
+            position_std_dev: std_dev,
+            heading_std_dev: std_dev,
+            capture_time: 1_000_000,
+        };
+
+        // Two cameras which disagree are weighted by their variances, so the
+        // estimate lies nearer the more precise one.
+        let measurements = [
+            measurement(0, 1.0, 0.1, 0.05),
+            measurement(1, 1.1, 0.2, 0.1),
+        ];
+
+        let fuser = Fuser::new(FusionConfig::default());
+        let fused = fuser.fuse_poses(&measurements, None).unwrap();
+
+        assert_eq!(fused.cameras, [0, 1]);
+        assert!((fused.pose.x - 1.02).abs() < 1e-9);
+        assert!((fused.pose.y - 2.).abs() < 1e-9);
+        assert!(fused.pose.heading > 0.1 && fused.pose.heading < 0.15);
+        assert!(fused.position_std_dev < 0.05);
+
+        // A camera far outside the others' uncertainty is left out.
+        let measurements = [
+            measurement(0, 1.0, 0.1, 0.05),
+            measurement(1, 1.1, 0.2, 0.1),
+            measurement(2, 3.0, 0.1, 0.1),
+        ];
+
+        let fused = fuser.fuse_poses(&measurements, None).unwrap();
+        assert_eq!(fused.cameras, [0, 1]);
+
+        // An older pose is moved by the robot's motion since it was captured.
+        let mut odometry = OdometryHistory::new(10);
+        odometry.record(990_000, FieldPose::new(0., 0., FRAC_PI_2));
+        odometry.record(1_000_000, FieldPose::new(0., 0.1, FRAC_PI_2));
+
+        let older = PoseMeasurement {
+            capture_time: 990_000,
+            ..measurement(1, 1.0, 0., 0.1)
+        };
+        let measurements = [older, measurement(0, 1.1, 0., 0.1)];
+        let fused = fuser.fuse_poses(&measurements, Some(&odometry)).unwrap();
+
+        assert_eq!(fused.cameras.len(), 2);
+        assert!((fused.pose.x - 1.1).abs() < 1e-9);
+        assert!((fused.pose.y - 2.).abs() < 1e-9);
+        assert_eq!(fused.capture_time, 1_000_000);
+    }
+}
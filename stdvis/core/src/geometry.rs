@@ -0,0 +1,655 @@
+//! Pure-Rust geometry on `Contour`s.
+//!
+//! Contours are treated as closed polygons in image coordinates, so the last
+//! point connects back to the first. Measurements do not depend on the
+//! winding order of the points.
+
+use crate::types::Contour;
+
+/// A point in image coordinates.
+pub type Point = (f32, f32);
+
+/// An axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BoundingBox {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl BoundingBox {
+    pub fn area(&self) -> f64 {
+        self.width as f64 * self.height as f64
+    }
+
+    pub fn center(&self) -> Point {
+        (self.x + self.width / 2., self.y + self.height / 2.)
+    }
+}
+
+/// A rectangle which may be rotated relative to the image axes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RotatedRect {
+    pub center: Point,
+    /// The length of the side along `angle`, followed by the other side.
+    pub size: (f32, f32),
+    /// The angle of the first side from the image `x` axis, in radians.
+    pub angle: f32,
+}
+
+impl RotatedRect {
+    pub fn area(&self) -> f64 {
+        self.size.0 as f64 * self.size.1 as f64
+    }
+
+    /// Returns the four corners of the rectangle.
+    pub fn corners(&self) -> [Point; 4] {
+        let (sin, cos) = self.angle.sin_cos();
+        let (half_w, half_h) = (self.size.0 / 2., self.size.1 / 2.);
+        let u = (cos * half_w, sin * half_w);
+        let v = (-sin * half_h, cos * half_h);
+        let (cx, cy) = self.center;
+
+        [
+            (cx - u.0 - v.0, cy - u.1 - v.1),
+            (cx + u.0 - v.0, cy + u.1 - v.1),
+            (cx + u.0 + v.0, cy + u.1 + v.1),
+            (cx - u.0 + v.0, cy - u.1 + v.1),
+        ]
+    }
+}
+
+/// Spatial and central image moments of a contour, up to the third order.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Moments {
+    pub m00: f64,
+    pub m10: f64,
+    pub m01: f64,
+    pub m20: f64,
+    pub m11: f64,
+    pub m02: f64,
+    pub m30: f64,
+    pub m21: f64,
+    pub m12: f64,
+    pub m03: f64,
+    pub mu20: f64,
+    pub mu11: f64,
+    pub mu02: f64,
+    pub mu30: f64,
+    pub mu21: f64,
+    pub mu12: f64,
+    pub mu03: f64,
+}
+
+impl Moments {
+    /// Returns the centroid described by these moments, if the area is
+    /// nonzero.
+    pub fn centroid(&self) -> Option<Point> {
+        if self.m00 == 0. {
+            return None;
+        }
+
+        Some(((self.m10 / self.m00) as f32, (self.m01 / self.m00) as f32))
+    }
+
+    /// Returns the angle of the major axis from the image `x` axis, in
+    /// radians.
+    pub fn orientation(&self) -> f64 {
+        0.5 * (2. * self.mu11).atan2(self.mu20 - self.mu02)
+    }
+}
+
+impl Contour {
+    pub fn new(points: Vec<Point>) -> Self {
+        Self { points }
+    }
+
+    /// Returns an iterator over the edges of the closed polygon.
+    fn edges(&self) -> impl Iterator<Item = (Point, Point)> + '_ {
+        let prev = self.points.iter().cycle().skip(self.points.len() - 1);
+        prev.zip(&self.points).map(|(a, b)| (*a, *b))
+    }
+
+    /// Returns the enclosed area, computed with the shoelace formula.
+    pub fn area(&self) -> f64 {
+        if self.points.len() < 3 {
+            return 0.;
+        }
+
+        (signed_area(&self.points) / 2.).abs()
+    }
+
+    /// Returns the length of the closed boundary.
+    pub fn perimeter(&self) -> f64 {
+        if self.points.len() < 2 {
+            return 0.;
+        }
+
+        self.edges().map(|(a, b)| distance(a, b)).sum()
+    }
+
+    /// Returns the image moments of the enclosed region.
+    pub fn moments(&self) -> Moments {
+        if self.points.len() < 3 {
+            return Moments::default();
+        }
+
+        let (mut a00, mut a10, mut a01) = (0., 0., 0.);
+        let (mut a20, mut a11, mut a02) = (0., 0., 0.);
+        let (mut a30, mut a21, mut a12, mut a03) = (0., 0., 0., 0.);
+
+        for ((x0, y0), (x1, y1)) in self.edges() {
+            let (x0, y0, x1, y1) = (x0 as f64, y0 as f64, x1 as f64, y1 as f64);
+
+            let dxy = x0 * y1 - x1 * y0;
+            let xs = x0 + x1;
+            let ys = y0 + y1;
+
+            a00 += dxy;
+            a10 += dxy * xs;
+            a01 += dxy * ys;
+            a20 += dxy * (x0 * xs + x1 * x1);
+            a11 += dxy * (x0 * (ys + y0) + x1 * (ys + y1));
+            a02 += dxy * (y0 * ys + y1 * y1);
+            a30 += dxy * xs * (x0 * x0 + x1 * x1);
+            a03 += dxy * ys * (y0 * y0 + y1 * y1);
+            a21 += dxy * (x0 * x0 * (3. * y0 + y1) + 2. * x1 * x0 * ys + x1 * x1 * (y0 + 3. * y1));
+            a12 += dxy * (y0 * y0 * (3. * x0 + x1) + 2. * y1 * y0 * xs + y1 * y1 * (x0 + 3. * x1));
+        }
+
+        // Clockwise contours produce negated moments.
+        let sign = if a00 < 0. { -1. } else { 1. };
+
+        let m00 = sign * a00 / 2.;
+        let m10 = sign * a10 / 6.;
+        let m01 = sign * a01 / 6.;
+        let m20 = sign * a20 / 12.;
+        let m11 = sign * a11 / 24.;
+        let m02 = sign * a02 / 12.;
+        let m30 = sign * a30 / 20.;
+        let m21 = sign * a21 / 60.;
+        let m12 = sign * a12 / 60.;
+        let m03 = sign * a03 / 20.;
+
+        let (cx, cy) = if m00 == 0. {
+            (0., 0.)
+        } else {
+            (m10 / m00, m01 / m00)
+        };
+
+        let mu20 = m20 - cx * m10;
+        let mu11 = m11 - cx * m01;
+        let mu02 = m02 - cy * m01;
+
+        Moments {
+            m00,
+            m10,
+            m01,
+            m20,
+            m11,
+            m02,
+            m30,
+            m21,
+            m12,
+            m03,
+            mu20,
+            mu11,
+            mu02,
+            mu30: m30 - cx * (3. * mu20 + cx * m10),
+            mu21: m21 - cx * (2. * mu11 + cx * m01) - cy * mu20,
+            mu12: m12 - cy * (2. * mu11 + cy * m10) - cx * mu02,
+            mu03: m03 - cy * (3. * mu02 + cy * m01),
+        }
+    }
+
+    /// Returns the centroid of the enclosed region. Degenerate contours fall
+    /// back to the mean of their points.
+    pub fn centroid(&self) -> Option<Point> {
+        if self.points.is_empty() {
+            return None;
+        }
+
+        self.moments().centroid().or_else(|| {
+            let n = self.points.len() as f32;
+            let (sx, sy) = self
+                .points
+                .iter()
+                .fold((0., 0.), |(sx, sy), (x, y)| (sx + x, sy + y));
+
+            Some((sx / n, sy / n))
+        })
+    }
+
+    /// Returns the smallest axis-aligned box containing every point.
+    pub fn bounding_box(&self) -> BoundingBox {
+        let mut points = self.points.iter();
+
+        let first = match points.next() {
+            Some(point) => *point,
+            None => return BoundingBox::default(),
+        };
+
+        let (min, max) = points.fold((first, first), |(min, max), &(x, y)| {
+            ((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y)))
+        });
+
+        BoundingBox {
+            x: min.0,
+            y: min.1,
+            width: max.0 - min.0,
+            height: max.1 - min.1,
+        }
+    }
+
+    /// Returns the convex hull, computed with Andrew's monotone chain
+    /// algorithm.
+    pub fn convex_hull(&self) -> Contour {
+        let mut points = self.points.clone();
+        points.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        points.dedup();
+
+        if points.len() < 3 {
+            return Contour::new(points);
+        }
+
+        let mut hull: Vec<Point> = Vec::with_capacity(points.len() * 2);
+
+        for pass in 0..2 {
+            let start = hull.len();
+
+            for &point in &points {
+                while hull.len() >= start + 2
+                    && cross(hull[hull.len() - 2], hull[hull.len() - 1], point) <= 0.
+                {
+                    hull.pop();
+                }
+                hull.push(point);
+            }
+
+            // The last point of each chain is the first point of the next.
+            hull.pop();
+
+            if pass == 0 {
+                points.reverse();
+            }
+        }
+
+        Contour::new(hull)
+    }
+
+    /// Returns the rotated rectangle of least area that contains every point.
+    pub fn min_area_rect(&self) -> RotatedRect {
+        let hull = self.convex_hull();
+
+        match hull.points.len() {
+            0 => return RotatedRect::default(),
+            1 => {
+                return RotatedRect {
+                    center: hull.points[0],
+                    ..RotatedRect::default()
+                }
+            }
+            _ => {}
+        }
+
+        let mut best = RotatedRect::default();
+        let mut best_area = f64::INFINITY;
+
+        for (a, b) in hull.edges() {
+            let length = distance(a, b) as f32;
+            if length == 0. {
+                continue;
+            }
+
+            let u = ((b.0 - a.0) / length, (b.1 - a.1) / length);
+            let v = (-u.1, u.0);
+
+            let (mut min_u, mut max_u) = (f32::INFINITY, f32::NEG_INFINITY);
+            let (mut min_v, mut max_v) = (f32::INFINITY, f32::NEG_INFINITY);
+
+            for &(x, y) in &hull.points {
+                let (dx, dy) = (x - a.0, y - a.1);
+                let pu = dx * u.0 + dy * u.1;
+                let pv = dx * v.0 + dy * v.1;
+
+                min_u = min_u.min(pu);
+                max_u = max_u.max(pu);
+                min_v = min_v.min(pv);
+                max_v = max_v.max(pv);
+            }
+
+            let size = (max_u - min_u, max_v - min_v);
+            let area = size.0 as f64 * size.1 as f64;
+
+            if area < best_area {
+                let mid_u = (min_u + max_u) / 2.;
+                let mid_v = (min_v + max_v) / 2.;
+
+                best_area = area;
+                best = RotatedRect {
+                    center: (
+                        a.0 + u.0 * mid_u + v.0 * mid_v,
+                        a.1 + u.1 * mid_u + v.1 * mid_v,
+                    ),
+                    size,
+                    angle: u.1.atan2(u.0),
+                };
+            }
+        }
+
+        best
+    }
+
+    /// Returns the ratio of the contour's area to the area of its convex hull.
+    pub fn solidity(&self) -> f64 {
+        let hull_area = self.convex_hull().area();
+
+        if hull_area == 0. {
+            return 0.;
+        }
+
+        self.area() / hull_area
+    }
+
+    /// Returns the ratio of the contour's area to the area of its bounding
+    /// box.
+    pub fn extent(&self) -> f64 {
+        let box_area = self.bounding_box().area();
+
+        if box_area == 0. {
+            return 0.;
+        }
+
+        self.area() / box_area
+    }
+
+    /// Returns the width of the bounding box divided by its height.
+    pub fn aspect_ratio(&self) -> f64 {
+        let bounds = self.bounding_box();
+
+        if bounds.height == 0. {
+            return 0.;
+        }
+
+        bounds.width as f64 / bounds.height as f64
+    }
+
+    /// Returns whether `point` lies inside the contour, using the even-odd
+    /// rule.
+    pub fn contains(&self, (px, py): Point) -> bool {
+        if self.points.len() < 3 {
+            return false;
+        }
+
+        self.edges().fold(false, |inside, ((x0, y0), (x1, y1))| {
+            if (y0 > py) != (y1 > py) && px < (x1 - x0) * (py - y0) / (y1 - y0) + x0 {
+                !inside
+            } else {
+                inside
+            }
+        })
+    }
+
+    /// Finds `count` corners of the contour, by approximating it with a
+    /// polygon whose tolerance is adjusted until it has that many vertices.
+    /// Quadrilaterals fall back to the corners of the minimum-area rectangle.
+    ///
+    /// The corners are returned in their order along the contour; use
+    /// [`order_corners`] to put them in a canonical order.
+    pub fn corners(&self, count: usize) -> Option<Vec<Point>> {
+        if self.points.len() == count {
+            return Some(self.points.clone());
+        }
+
+        let (mut low, mut high) = (0., self.perimeter() as f32 / 2.);
+
+        for _ in 0..24 {
+            let epsilon = (low + high) / 2.;
+            let approx = self.approx_poly(epsilon);
+
+            match approx.points.len().cmp(&count) {
+                std::cmp::Ordering::Equal => return Some(approx.points),
+                std::cmp::Ordering::Greater => low = epsilon,
+                std::cmp::Ordering::Less => high = epsilon,
+            }
+        }
+
+        if count == 4 && self.points.len() >= 3 {
+            return Some(self.min_area_rect().corners().to_vec());
+        }
+
+        None
+    }
+
+    /// Approximates the contour with fewer vertices using the Douglas–Peucker
+    /// algorithm, such that no point is further than `epsilon` from the
+    /// result.
+    pub fn approx_poly(&self, epsilon: f32) -> Contour {
+        let points = &self.points;
+
+        if points.len() < 3 {
+            return Contour::new(points.clone());
+        }
+
+        // Split the closed curve at the point furthest from the first, so that
+        // each half can be simplified as an open curve.
+        let far = (1..points.len())
+            .max_by(|&a, &b| {
+                distance(points[0], points[a])
+                    .partial_cmp(&distance(points[0], points[b]))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap();
+
+        let mut keep = vec![false; points.len()];
+        keep[0] = true;
+        keep[far] = true;
+
+        let mut chain: Vec<usize> = (far..points.len()).collect();
+        chain.push(0);
+
+        douglas_peucker(points, &(0..=far).collect::<Vec<_>>(), epsilon, &mut keep);
+        douglas_peucker(points, &chain, epsilon, &mut keep);
+
+        Contour::new(
+            points
+                .iter()
+                .zip(keep)
+                .filter(|(_, keep)| *keep)
+                .map(|(point, _)| *point)
+                .collect(),
+        )
+    }
+}
+
+/// Orders corners clockwise (as seen in the image) around their mean,
+/// starting from the corner nearest the top-left.
+///
+/// `rotation` is the expected clockwise rotation of the target in the image,
+/// in radians, such as that caused by the camera's roll. The ordering is
+/// stable for targets within 45 degrees of that rotation.
+pub fn order_corners(mut corners: Vec<Point>, rotation: f32) -> Vec<Point> {
+    if corners.is_empty() {
+        return corners;
+    }
+
+    let n = corners.len() as f32;
+    let (cx, cy) = corners
+        .iter()
+        .fold((0., 0.), |(sx, sy), (x, y)| (sx + x / n, sy + y / n));
+
+    // With the image's `y` axis pointing down, increasing angles run
+    // clockwise.
+    let angle = |(x, y): &Point| (y - cy).atan2(x - cx);
+    corners.sort_by(|a, b| {
+        angle(a)
+            .partial_cmp(&angle(b))
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    let top_left = -3. * std::f32::consts::FRAC_PI_4 + rotation;
+    let offset = |point: &Point| {
+        let diff = (angle(point) - top_left).rem_euclid(std::f32::consts::TAU);
+        diff.min(std::f32::consts::TAU - diff)
+    };
+
+    let start = corners
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| {
+            offset(a)
+                .partial_cmp(&offset(b))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .map_or(0, |(idx, _)| idx);
+
+    corners.rotate_left(start);
+    corners
+}
+
+/// Marks the points of the open polyline `chain` (given as indices into
+/// `points`) which are kept by Douglas–Peucker simplification.
+fn douglas_peucker(points: &[Point], chain: &[usize], epsilon: f32, keep: &mut [bool]) {
+    if chain.len() < 3 {
+        return;
+    }
+
+    let (start, end) = (points[chain[0]], points[chain[chain.len() - 1]]);
+
+    let (split, max_dist) = chain[1..chain.len() - 1]
+        .iter()
+        .enumerate()
+        .map(|(i, &idx)| (i + 1, segment_distance(points[idx], start, end)))
+        .fold(
+            (0, 0.),
+            |best, curr| if curr.1 > best.1 { curr } else { best },
+        );
+
+    if max_dist > epsilon as f64 {
+        keep[chain[split]] = true;
+
+        douglas_peucker(points, &chain[..=split], epsilon, keep);
+        douglas_peucker(points, &chain[split..], epsilon, keep);
+    }
+}
+
+/// Returns twice the signed area of a polygon.
+fn signed_area(points: &[Point]) -> f64 {
+    let prev = points.iter().cycle().skip(points.len() - 1);
+
+    prev.zip(points)
+        .map(|(a, b)| a.0 as f64 * b.1 as f64 - b.0 as f64 * a.1 as f64)
+        .sum()
+}
+
+/// Returns the z component of the cross product of `ab` and `ac`.
+fn cross(a: Point, b: Point, c: Point) -> f32 {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+/// Returns the Euclidean distance between two points.
+pub fn distance(a: Point, b: Point) -> f64 {
+    (b.0 as f64 - a.0 as f64).hypot(b.1 as f64 - a.1 as f64)
+}
+
+/// Returns the distance from `point` to the segment between `start` and
+/// `end`.
+pub fn segment_distance(point: Point, start: Point, end: Point) -> f64 {
+    let (px, py) = (point.0 as f64, point.1 as f64);
+    let (sx, sy) = (start.0 as f64, start.1 as f64);
+    let (dx, dy) = (end.0 as f64 - sx, end.1 as f64 - sy);
+
+    let length_sq = dx * dx + dy * dy;
+    if length_sq == 0. {
+        return distance(point, start);
+    }
+
+    let t = (((px - sx) * dx + (py - sy) * dy) / length_sq).clamp(0., 1.);
+
+    (px - (sx + t * dx)).hypot(py - (sy + t * dy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square() -> Contour {
+        Contour::new(vec![(0., 0.), (4., 0.), (4., 2.), (0., 2.)])
+    }
+
+    #[test]
+    fn test_basic_measurements() {
+        let contour = square();
+
+        assert_eq!(contour.area(), 8.);
+        assert_eq!(contour.perimeter(), 12.);
+        assert_eq!(contour.centroid(), Some((2., 1.)));
+        assert_eq!(contour.aspect_ratio(), 2.);
+        assert_eq!(contour.extent(), 1.);
+        assert_eq!(contour.solidity(), 1.);
+        assert!(contour.contains((1., 1.)));
+        assert!(!contour.contains((5., 1.)));
+
+        let moments = contour.moments();
+        assert!((moments.mu20 - 8. * 16. / 12.).abs() < 1e-9);
+        assert!(moments.mu11.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_hull_and_approximation() {
+        let contour = Contour::new(vec![
+            (0., 0.),
+            (2., 0.1),
+            (4., 0.),
+            (2., 1.),
+            (4., 4.),
+            (0., 4.),
+        ]);
+
+        let hull = contour.convex_hull();
+        assert_eq!(hull.points.len(), 4);
+        assert_eq!(hull.area(), 16.);
+        assert!(contour.solidity() < 1.);
+
+        let approx = contour.approx_poly(0.5);
+        assert_eq!(approx.points.len(), 5);
+        assert!(!approx.points.contains(&(2., 0.1)));
+    }
+
+    #[test]
+    fn test_corner_ordering() {
+        let contour = Contour::new(vec![
+            (10., 0.),
+            (12., 0.5),
+            (20., 10.),
+            (10., 20.),
+            (0., 10.),
+            (1., 9.),
+        ]);
+
+        let corners = contour.corners(4).unwrap();
+        assert_eq!(corners.len(), 4);
+
+        // A square rotated by 30 degrees keeps its top-left corner first.
+        let (sin, cos) = 30f32.to_radians().sin_cos();
+        let rotate = |(x, y): Point| (x * cos - y * sin, x * sin + y * cos);
+        let square = [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)];
+
+        let shuffled = vec![square[2], square[0], square[3], square[1]];
+        let ordered = order_corners(shuffled.into_iter().map(rotate).collect(), 0.);
+        assert_eq!(
+            ordered,
+            square.iter().copied().map(rotate).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_min_area_rect() {
+        let diamond = Contour::new(vec![(0., 2.), (2., 0.), (4., 2.), (2., 4.)]);
+        let rect = diamond.min_area_rect();
+
+        assert!((rect.area() - 8.).abs() < 1e-4);
+        assert!((rect.center.0 - 2.).abs() < 1e-4 && (rect.center.1 - 2.).abs() < 1e-4);
+    }
+}
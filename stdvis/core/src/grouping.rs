@@ -0,0 +1,527 @@
+//! Strategies for arranging contours into the `ContourGroup`s that make up
+//! multi-piece targets.
+//!
+//! Every strategy numbers its groups afresh in each frame, from left to right
+//! by the mean centroid of their contours. The ids only tell apart the groups
+//! within one frame: a target's id changes whenever another appears or
+//! disappears to its left, so they can't be used to follow a target across
+//! frames.
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    geometry::Point,
+    traits::ContourGrouper,
+    types::{CameraConfig, Contour, ContourGroup},
+};
+
+/// A configurable choice of grouping strategy.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum GroupingStrategy {
+    Single(SingleGrouping),
+    Pairs(PairGrouping),
+    Arc(ArcGrouping),
+    Cluster(ClusterGrouping),
+}
+
+impl Default for GroupingStrategy {
+    fn default() -> Self {
+        GroupingStrategy::Single(SingleGrouping)
+    }
+}
+
+impl ContourGrouper for GroupingStrategy {
+    fn group<'src>(
+        &self,
+        camera: &'src CameraConfig,
+        contours: Vec<Contour>,
+    ) -> Vec<ContourGroup<'src>> {
+        match self {
+            GroupingStrategy::Single(strategy) => strategy.group(camera, contours),
+            GroupingStrategy::Pairs(strategy) => strategy.group(camera, contours),
+            GroupingStrategy::Arc(strategy) => strategy.group(camera, contours),
+            GroupingStrategy::Cluster(strategy) => strategy.group(camera, contours),
+        }
+    }
+}
+
+/// Places every contour in a group of its own.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct SingleGrouping;
+
+impl ContourGrouper for SingleGrouping {
+    fn group<'src>(
+        &self,
+        camera: &'src CameraConfig,
+        contours: Vec<Contour>,
+    ) -> Vec<ContourGroup<'src>> {
+        assign_ids(camera, contours.into_iter().map(|contour| vec![contour]))
+    }
+}
+
+/// Pairs neighbouring strips which tilt towards (or away from) each other,
+/// as in targets made of two angled pieces of tape.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PairGrouping {
+    /// Whether the tops of the strips lean towards each other, rather than
+    /// their bottoms.
+    #[serde(default = "PairGrouping::default_inward")]
+    pub inward: bool,
+    /// The smallest tilt from vertical, in radians, for a strip to count as
+    /// leaning.
+    #[serde(default)]
+    pub min_tilt: f64,
+    /// The largest horizontal distance between the strips' centroids, as a
+    /// multiple of their mean height.
+    #[serde(default)]
+    pub max_spacing: Option<f64>,
+}
+
+impl PairGrouping {
+    fn default_inward() -> bool {
+        true
+    }
+}
+
+impl Default for PairGrouping {
+    fn default() -> Self {
+        Self {
+            inward: true,
+            min_tilt: 0.,
+            max_spacing: None,
+        }
+    }
+}
+
+impl ContourGrouper for PairGrouping {
+    fn group<'src>(
+        &self,
+        camera: &'src CameraConfig,
+        contours: Vec<Contour>,
+    ) -> Vec<ContourGroup<'src>> {
+        let mut strips = measure_sorted(contours);
+
+        // A positive tilt means the top of the strip leans right.
+        let sign = if self.inward { 1. } else { -1. };
+        let is_left = |strip: &Measured| sign * strip.tilt > self.min_tilt;
+        let is_right = |strip: &Measured| sign * strip.tilt < -self.min_tilt;
+
+        let mut pairs = Vec::new();
+        let mut idx = 0;
+
+        while idx + 1 < strips.len() {
+            let (left, right) = (&strips[idx], &strips[idx + 1]);
+            let spacing = (right.centroid.0 - left.centroid.0) as f64;
+            let mean_height = (left.height + right.height) / 2.;
+
+            let close_enough = self
+                .max_spacing
+                .iter()
+                .all(|&max| spacing <= max * mean_height);
+
+            if is_left(left) && is_right(right) && close_enough {
+                pairs.push((idx, idx + 1));
+                idx += 2;
+            } else {
+                idx += 1;
+            }
+        }
+
+        let groups = pairs
+            .into_iter()
+            .rev()
+            .map(|(left, right)| {
+                let right = strips.remove(right).contour;
+                let left = strips.remove(left).contour;
+                vec![left, right]
+            })
+            .collect::<Vec<_>>();
+
+        assign_ids(camera, groups)
+    }
+}
+
+/// Finds runs of similarly spaced strips whose centroids lie along a smooth
+/// arc, as in targets made of several pieces of tape around a ring.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ArcGrouping {
+    /// The fewest strips that form a group.
+    pub min_count: usize,
+    /// The most strips that form a group. Longer runs are trimmed to the
+    /// window which best fits an arc.
+    pub max_count: usize,
+    /// The largest horizontal distance between neighbouring centroids, as a
+    /// multiple of the strips' mean width.
+    pub max_spacing: f64,
+    /// The largest vertical deviation of any centroid from the fitted arc, as
+    /// a multiple of the strips' mean height.
+    pub max_residual: f64,
+}
+
+impl Default for ArcGrouping {
+    fn default() -> Self {
+        Self {
+            min_count: 3,
+            max_count: 5,
+            max_spacing: 3.,
+            max_residual: 0.5,
+        }
+    }
+}
+
+impl ContourGrouper for ArcGrouping {
+    fn group<'src>(
+        &self,
+        camera: &'src CameraConfig,
+        contours: Vec<Contour>,
+    ) -> Vec<ContourGroup<'src>> {
+        let strips = measure_sorted(contours);
+        let min_count = self.min_count.max(1);
+        let max_count = self.max_count.max(min_count);
+
+        // Split the strips into runs of neighbours that are close enough.
+        let mut runs: Vec<Vec<Measured>> = Vec::new();
+        for strip in strips {
+            let continues = match runs.last().and_then(|run| run.last()) {
+                Some(prev) => {
+                    let spacing = (strip.centroid.0 - prev.centroid.0) as f64;
+                    spacing <= self.max_spacing * (strip.width + prev.width) / 2.
+                }
+                None => false,
+            };
+
+            match runs.last_mut() {
+                Some(run) if continues => run.push(strip),
+                _ => runs.push(vec![strip]),
+            }
+        }
+
+        let mut groups = Vec::new();
+
+        for mut run in runs {
+            if run.len() < min_count {
+                continue;
+            }
+
+            let window = run.len().min(max_count);
+            let (start, residual) = (0..=run.len() - window)
+                .map(|start| (start, arc_residual(&run[start..start + window])))
+                .fold((0, f64::INFINITY), |best, curr| {
+                    if curr.1 < best.1 {
+                        curr
+                    } else {
+                        best
+                    }
+                });
+
+            let mean_height = run[start..start + window]
+                .iter()
+                .map(|strip| strip.height)
+                .sum::<f64>()
+                / window as f64;
+
+            if residual <= self.max_residual * mean_height {
+                groups.push(
+                    run.drain(start..start + window)
+                        .map(|strip| strip.contour)
+                        .collect(),
+                );
+            }
+        }
+
+        assign_ids(camera, groups)
+    }
+}
+
+/// Clusters contours whose centroids are within a given distance of each
+/// other, directly or through other contours in the cluster.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClusterGrouping {
+    /// The largest distance between neighbouring centroids, in pixels.
+    pub max_distance: f64,
+    /// The fewest contours that form a group.
+    #[serde(default = "ClusterGrouping::default_min_count")]
+    pub min_count: usize,
+    /// The most contours that form a group.
+    #[serde(default)]
+    pub max_count: Option<usize>,
+}
+
+impl ClusterGrouping {
+    fn default_min_count() -> usize {
+        1
+    }
+}
+
+impl ContourGrouper for ClusterGrouping {
+    fn group<'src>(
+        &self,
+        camera: &'src CameraConfig,
+        contours: Vec<Contour>,
+    ) -> Vec<ContourGroup<'src>> {
+        let centroids = contours
+            .iter()
+            .map(|contour| contour.centroid().unwrap_or_default())
+            .collect::<Vec<_>>();
+
+        let mut parents = (0..contours.len()).collect::<Vec<_>>();
+
+        fn root(parents: &mut [usize], mut idx: usize) -> usize {
+            while parents[idx] != idx {
+                parents[idx] = parents[parents[idx]];
+                idx = parents[idx];
+            }
+            idx
+        }
+
+        for a in 0..centroids.len() {
+            for b in a + 1..centroids.len() {
+                if crate::geometry::distance(centroids[a], centroids[b]) <= self.max_distance {
+                    let (root_a, root_b) = (root(&mut parents, a), root(&mut parents, b));
+                    parents[root_b] = root_a;
+                }
+            }
+        }
+
+        let mut clusters: Vec<Vec<Contour>> = Vec::new();
+        let mut cluster_of_root = vec![None; contours.len()];
+
+        for (idx, contour) in contours.into_iter().enumerate() {
+            let root = root(&mut parents, idx);
+            let cluster = *cluster_of_root[root].get_or_insert_with(|| {
+                clusters.push(Vec::new());
+                clusters.len() - 1
+            });
+
+            clusters[cluster].push(contour);
+        }
+
+        let groups = clusters.into_iter().filter(|cluster| {
+            cluster.len() >= self.min_count
+                && self.max_count.iter().all(|&max| cluster.len() <= max)
+        });
+
+        assign_ids(camera, groups)
+    }
+}
+
+/// A contour along with the measurements used for grouping.
+struct Measured {
+    contour: Contour,
+    centroid: Point,
+    width: f64,
+    height: f64,
+    tilt: f64,
+}
+
+/// Measures each contour and sorts them from left to right.
+fn measure_sorted(contours: Vec<Contour>) -> Vec<Measured> {
+    let mut measured = contours
+        .into_iter()
+        .map(|contour| {
+            let bounds = contour.bounding_box();
+            let orientation = contour.moments().orientation();
+
+            // Take the direction of the major axis which points up the
+            // image, and measure its angle from vertical.
+            let (sin, cos) = orientation.sin_cos();
+            let (dx, dy) = if sin > 0. { (-cos, -sin) } else { (cos, sin) };
+
+            Measured {
+                centroid: contour.centroid().unwrap_or_default(),
+                width: bounds.width as f64,
+                height: bounds.height as f64,
+                tilt: dx.atan2(-dy),
+                contour,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    measured.sort_by(|a, b| {
+        a.centroid
+            .0
+            .partial_cmp(&b.centroid.0)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    measured
+}
+
+/// Returns the largest vertical distance of any centroid from the parabola
+/// that best fits them all.
+fn arc_residual(strips: &[Measured]) -> f64 {
+    if strips.len() < 4 {
+        return 0.;
+    }
+
+    // Solve the normal equations for y = a * x^2 + b * x + c.
+    let mut sums = [0.; 5];
+    let mut rhs = [0.; 3];
+
+    for strip in strips {
+        let (x, y) = (strip.centroid.0 as f64, strip.centroid.1 as f64);
+        let mut power = 1.;
+
+        for (idx, sum) in sums.iter_mut().enumerate() {
+            *sum += power;
+            if idx < 3 {
+                rhs[idx] += power * y;
+            }
+            power *= x;
+        }
+    }
+
+    let matrix = [
+        [sums[4], sums[3], sums[2]],
+        [sums[3], sums[2], sums[1]],
+        [sums[2], sums[1], sums[0]],
+    ];
+    let rhs = [rhs[2], rhs[1], rhs[0]];
+
+    let det = determinant(&matrix);
+    if det.abs() < f64::EPSILON {
+        return f64::INFINITY;
+    }
+
+    let mut coeffs = [0.; 3];
+    for (col, coeff) in coeffs.iter_mut().enumerate() {
+        let mut replaced = matrix;
+        for row in 0..3 {
+            replaced[row][col] = rhs[row];
+        }
+        *coeff = determinant(&replaced) / det;
+    }
+
+    strips
+        .iter()
+        .map(|strip| {
+            let (x, y) = (strip.centroid.0 as f64, strip.centroid.1 as f64);
+            (coeffs[0] * x * x + coeffs[1] * x + coeffs[2] - y).abs()
+        })
+        .fold(0., f64::max)
+}
+
+fn determinant(m: &[[f64; 3]; 3]) -> f64 {
+    m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+}
+
+/// Builds groups numbered from left to right by their mean centroid. Groups
+/// beyond the last id are dropped.
+fn assign_ids<'src>(
+    camera: &'src CameraConfig,
+    groups: impl IntoIterator<Item = Vec<Contour>>,
+) -> Vec<ContourGroup<'src>> {
+    let mut groups = groups
+        .into_iter()
+        .map(|contours| {
+            let (sum_x, sum_y) = contours
+                .iter()
+                .filter_map(|contour| contour.centroid())
+                .fold((0., 0.), |(sx, sy), (x, y)| (sx + x, sy + y));
+            let n = contours.len().max(1) as f32;
+
+            ((sum_x / n, sum_y / n), contours)
+        })
+        .collect::<Vec<_>>();
+
+    groups.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+    if groups.len() > usize::from(u16::MAX) + 1 {
+        warn!(
+            groups = groups.len(),
+            "too many groups to number, dropping the rightmost"
+        );
+    }
+
+    groups
+        .into_iter()
+        .enumerate()
+        .map_while(|(idx, (_, contours))| {
+            Some(ContourGroup {
+                id: u16::try_from(idx).ok()?,
+                camera,
+                contours,
+                fiducial: None,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip(x: f32, y: f32, lean: f32) -> Contour {
+        Contour::new(vec![
+            (x - 2., y + 10.),
+            (x + 2., y + 10.),
+            (x + 2. + lean, y - 10.),
+            (x - 2. + lean, y - 10.),
+        ])
+    }
+
+    #[test]
+    fn test_assign_ids_overflow() {
+        let camera = CameraConfig::default();
+        let count = usize::from(u16::MAX) + 2;
+
+        let groups = assign_ids(
+            &camera,
+            (0..count).map(|x| vec![Contour::new(vec![(x as f32, 0.)])]),
+        );
+
+        assert_eq!(groups.len(), count - 1);
+        assert_eq!(groups.last().unwrap().id, u16::MAX);
+    }
+
+    #[test]
+    fn test_pair_grouping() {
+        let camera = CameraConfig::default();
+
+        let contours = vec![
+            strip(130., 50., -5.),
+            strip(10., 50., -5.),
+            strip(30., 50., 5.),
+            strip(50., 50., -5.),
+            strip(110., 50., 5.),
+        ];
+
+        let groups = PairGrouping::default().group(&camera, contours);
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].id, 0);
+        assert_eq!(groups[0].contours[0], strip(30., 50., 5.));
+        assert_eq!(groups[1].id, 1);
+        assert_eq!(groups[1].contours[1], strip(130., 50., -5.));
+    }
+
+    #[test]
+    fn test_arc_and_cluster_grouping() {
+        let camera = CameraConfig::default();
+
+        let arc = (0..5)
+            .map(|idx| {
+                let x = idx as f32 * 10.;
+                strip(x, 50. + (x - 20.).powi(2) / 40., 0.)
+            })
+            .chain(std::iter::once(strip(200., 50., 0.)))
+            .collect::<Vec<_>>();
+
+        let groups = ArcGrouping::default().group(&camera, arc.clone());
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].contours.len(), 5);
+
+        let clusters = ClusterGrouping {
+            max_distance: 15.,
+            min_count: 1,
+            max_count: None,
+        }
+        .group(&camera, arc);
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(clusters[0].contours.len(), 5);
+        assert_eq!(clusters[1].contours.len(), 1);
+    }
+}
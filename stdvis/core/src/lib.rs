@@ -1,5 +1,20 @@
+pub mod analyzers;
+pub mod field;
+pub mod filter;
+pub mod fusion;
+pub mod geometry;
+pub mod grouping;
+pub mod metrics;
+pub mod model;
+pub mod odometry;
+pub mod pipeline;
+pub mod smoothing;
+pub mod timing;
 pub mod traits;
+pub mod transform;
+pub mod tracking;
 pub mod types;
+pub mod uncertainty;
 
 #[cfg(test)]
 mod tests {}
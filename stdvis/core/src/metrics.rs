@@ -0,0 +1,455 @@
+//! Rolling statistics on how long each stage of a pipeline takes, for telling
+//! whether a drop in frame rate comes from the camera or from processing.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::pipeline::Frame;
+
+/// A part of a frame's processing which is timed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Grab,
+    Preprocess,
+    Extract,
+    Filter,
+    Group,
+    Analyze,
+    Inspect,
+    Postprocess,
+    Output,
+    /// Every stage together.
+    Total,
+    /// The time from a frame's capture until its targets were output.
+    Latency,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 11] = [
+        Stage::Grab,
+        Stage::Preprocess,
+        Stage::Extract,
+        Stage::Filter,
+        Stage::Group,
+        Stage::Analyze,
+        Stage::Inspect,
+        Stage::Postprocess,
+        Stage::Output,
+        Stage::Total,
+        Stage::Latency,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Grab => "grab",
+            Stage::Preprocess => "preprocess",
+            Stage::Extract => "extract",
+            Stage::Filter => "filter",
+            Stage::Group => "group",
+            Stage::Analyze => "analyze",
+            Stage::Inspect => "inspect",
+            Stage::Postprocess => "postprocess",
+            Stage::Output => "output",
+            Stage::Total => "total",
+            Stage::Latency => "latency",
+        }
+    }
+
+    fn duration(self, frame: &Frame) -> Duration {
+        let timings = &frame.timings;
+
+        match self {
+            Stage::Grab => timings.grab,
+            Stage::Preprocess => timings.preprocess,
+            Stage::Extract => timings.extract,
+            Stage::Filter => timings.filter,
+            Stage::Group => timings.group,
+            Stage::Analyze => timings.analyze,
+            Stage::Inspect => timings.inspect,
+            Stage::Postprocess => timings.postprocess,
+            Stage::Output => timings.output,
+            Stage::Total => timings.total(),
+            Stage::Latency => frame.latency,
+        }
+    }
+}
+
+/// The number of samples in a bucket of a `RollingHistogram`, which holds
+/// every sample no longer than `upper` and longer than the previous bucket's.
+/// The last bucket has no upper bound.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bucket {
+    pub upper: Option<Duration>,
+    pub count: usize,
+}
+
+/// The durations of the most recent samples of a measurement.
+#[derive(Clone, Debug)]
+pub struct RollingHistogram {
+    samples: VecDeque<Duration>,
+    capacity: usize,
+}
+
+impl RollingHistogram {
+    /// The number of bounded buckets, whose upper bounds double from 250 µs.
+    const BUCKET_COUNT: u32 = 12;
+    const FIRST_BUCKET: Duration = Duration::from_micros(250);
+
+    /// Creates a histogram of the last `capacity` samples.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn record(&mut self, sample: Duration) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back(sample);
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Returns the sample below which `fraction` of the samples fall, using
+    /// the nearest sample.
+    pub fn percentile(&self, fraction: f64) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted = self.samples.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+
+        let rank = (fraction.clamp(0., 1.) * (sorted.len() - 1) as f64).round();
+        Some(sorted[rank as usize])
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        Some(self.samples.iter().sum::<Duration>() / self.samples.len() as u32)
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.samples.iter().copied().max()
+    }
+
+    /// Counts the samples in exponentially widening buckets.
+    pub fn buckets(&self) -> Vec<Bucket> {
+        let mut buckets = (0..Self::BUCKET_COUNT)
+            .map(|idx| Bucket {
+                upper: Some(Self::FIRST_BUCKET * 2u32.pow(idx)),
+                count: 0,
+            })
+            .chain([Bucket {
+                upper: None,
+                count: 0,
+            }])
+            .collect::<Vec<_>>();
+
+        for &sample in &self.samples {
+            let idx = buckets
+                .iter()
+                .position(|bucket| bucket.upper.iter().all(|&upper| sample <= upper))
+                .unwrap_or(buckets.len() - 1);
+
+            buckets[idx].count += 1;
+        }
+
+        buckets
+    }
+}
+
+/// Parameters for `PipelineMetrics`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// The number of recent frames that statistics are computed over.
+    #[serde(default = "MetricsConfig::default_window")]
+    pub window: usize,
+    /// How often a report is produced, in seconds.
+    #[serde(default = "MetricsConfig::default_report_interval")]
+    pub report_interval: f64,
+}
+
+impl MetricsConfig {
+    fn default_window() -> usize {
+        300
+    }
+
+    fn default_report_interval() -> f64 {
+        5.
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            window: Self::default_window(),
+            report_interval: Self::default_report_interval(),
+        }
+    }
+}
+
+/// Statistics on one stage, in milliseconds.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StageSummary {
+    pub stage: &'static str,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+/// Statistics on a pipeline's recent frames.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct MetricsSnapshot {
+    /// The number of frames processed since the metrics were created.
+    pub frames: u64,
+    /// The number of frames which failed to process.
+    pub errors: u64,
+    /// The number of frames skipped between those processed, by their
+    /// sequence numbers.
+    pub dropped: u64,
+    /// The rate at which recent frames were processed.
+    pub fps: f64,
+    pub stages: Vec<StageSummary>,
+}
+
+impl fmt::Display for MetricsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.1} fps, {} frames, {} dropped, {} errors",
+            self.fps, self.frames, self.dropped, self.errors
+        )?;
+
+        for stage in &self.stages {
+            write!(
+                f,
+                "; {} p50 {:.2} ms p99 {:.2} ms",
+                stage.stage, stage.p50, stage.p99
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Records the timings of every frame run through a pipeline.
+pub struct PipelineMetrics {
+    config: MetricsConfig,
+    stages: Vec<RollingHistogram>,
+    completions: VecDeque<Instant>,
+    frames: u64,
+    errors: u64,
+    dropped: u64,
+    last_sequence: Option<u32>,
+    last_report: Instant,
+}
+
+impl PipelineMetrics {
+    pub fn new(config: MetricsConfig) -> Self {
+        let stages = Stage::ALL
+            .iter()
+            .map(|_| RollingHistogram::new(config.window))
+            .collect();
+
+        Self {
+            stages,
+            completions: VecDeque::with_capacity(config.window),
+            frames: 0,
+            errors: 0,
+            dropped: 0,
+            last_sequence: None,
+            last_report: Instant::now(),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &MetricsConfig {
+        &self.config
+    }
+
+    /// Records a frame which was processed just now.
+    pub fn record(&mut self, frame: &Frame) {
+        for (stage, histogram) in Stage::ALL.iter().zip(&mut self.stages) {
+            histogram.record(stage.duration(frame));
+        }
+
+        if let Some(last) = self.last_sequence {
+            self.dropped += frame.sequence.saturating_sub(last).saturating_sub(1) as u64;
+        }
+        self.last_sequence = Some(frame.sequence);
+
+        if self.completions.len() == self.config.window.max(2) {
+            self.completions.pop_front();
+        }
+        self.completions.push_back(Instant::now());
+
+        self.frames += 1;
+    }
+
+    /// Records a frame which failed to process.
+    pub fn record_error(&mut self) {
+        self.errors += 1;
+    }
+
+    pub fn histogram(&self, stage: Stage) -> &RollingHistogram {
+        let idx = Stage::ALL.iter().position(|&other| other == stage).unwrap();
+        &self.stages[idx]
+    }
+
+    /// The rate at which recent frames were processed.
+    pub fn fps(&self) -> f64 {
+        match (self.completions.front(), self.completions.back()) {
+            (Some(first), Some(last)) if last > first => {
+                (self.completions.len() - 1) as f64 / (*last - *first).as_secs_f64()
+            }
+            _ => 0.,
+        }
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let millis = |duration: Option<Duration>| {
+            duration.map_or(0., |duration| duration.as_secs_f64() * 1e3)
+        };
+
+        let stages = Stage::ALL
+            .iter()
+            .zip(&self.stages)
+            .map(|(stage, histogram)| StageSummary {
+                stage: stage.name(),
+                mean: millis(histogram.mean()),
+                p50: millis(histogram.percentile(0.5)),
+                p90: millis(histogram.percentile(0.9)),
+                p99: millis(histogram.percentile(0.99)),
+                max: millis(histogram.max()),
+            })
+            .collect();
+
+        MetricsSnapshot {
+            frames: self.frames,
+            errors: self.errors,
+            dropped: self.dropped,
+            fps: self.fps(),
+            stages,
+        }
+    }
+
+    /// Returns a snapshot if the report interval has passed since the last
+    /// one, for periodic logging.
+    pub fn report(&mut self) -> Option<MetricsSnapshot> {
+        let interval = Duration::from_secs_f64(self.config.report_interval.max(0.));
+
+        if self.last_report.elapsed() < interval {
+            return None;
+        }
+
+        self.last_report = Instant::now();
+        Some(self.snapshot())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pipeline::{
+            tests::{ColumnAnalyzer, PixelExtractor, TestCamera},
+            Pipeline, StageTimings,
+        },
+        types::CameraConfig,
+    };
+
+    #[test]
+    fn test_metrics() {
+        let mut metrics = PipelineMetrics::new(MetricsConfig {
+            window: 100,
+            report_interval: 0.,
+        });
+
+        for (idx, sequence) in (1..=100).chain([103]).enumerate() {
+            let frame = Frame {
+                sequence,
+                targets: Vec::new(),
+                rejections: Vec::new(),
+                failures: Vec::new(),
+                timings: StageTimings {
+                    extract: Duration::from_millis(idx as u64 % 10 + 1),
+                    ..StageTimings::default()
+                },
+                latency: Duration::from_millis(20),
+            };
+
+            metrics.record(&frame);
+        }
+        metrics.record_error();
+
+        let extract = metrics.histogram(Stage::Extract);
+        assert_eq!(extract.len(), 100);
+        assert_eq!(extract.percentile(0.), Some(Duration::from_millis(1)));
+        assert_eq!(extract.max(), Some(Duration::from_millis(10)));
+        assert_eq!(extract.buckets()[3].count, 10);
+
+        let snapshot = metrics.report().unwrap();
+        assert_eq!(snapshot.frames, 101);
+        assert_eq!(snapshot.dropped, 2);
+        assert_eq!(snapshot.errors, 1);
+
+        let latency = snapshot
+            .stages
+            .iter()
+            .find(|s| s.stage == "latency")
+            .unwrap();
+        assert!((latency.p99 - 20.).abs() < 1e-9);
+        assert!(snapshot.to_string().contains("2 dropped"));
+    }
+
+    #[test]
+    fn test_skipped_frames() {
+        let camera = TestCamera {
+            config: CameraConfig::default(),
+            frames: 0,
+        };
+        let mut pipeline =
+            Pipeline::new(camera, Box::new(PixelExtractor), Box::new(ColumnAnalyzer));
+        let mut metrics = PipelineMetrics::new(MetricsConfig::default());
+
+        metrics.record(&pipeline.run_frame().unwrap());
+
+        // The camera skips two frames, as when its driver discards them while
+        // the pipeline is busy.
+        pipeline.camera_mut().frames += 2;
+        metrics.record(&pipeline.run_frame().unwrap());
+
+        // A frame which fails is counted as an error, not as dropped.
+        metrics.record_error();
+        metrics.record(&pipeline.run_frame().unwrap());
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.frames, 3);
+        assert_eq!(snapshot.dropped, 2);
+        assert_eq!(snapshot.errors, 1);
+    }
+}
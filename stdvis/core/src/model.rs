@@ -0,0 +1,307 @@
+//! Definitions of the physical geometry of vision targets.
+//!
+//! A model lists the corners of each piece of a target (such as a strip of
+//! retroreflective tape) in the model frame, in meters. The model frame has
+//! `x` pointing right across the target's face, `y` pointing up and `z`
+//! pointing out of the face towards the viewer. Pieces are listed from left to
+//! right, and each piece's corners run clockwise from the top-left as seen
+//! facing the target.
+
+use std::{f64::consts::PI, fs, path::Path};
+
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::transform::Vector3;
+
+/// The number of meters in an inch, for models defined in imperial units.
+pub const INCH: f64 = 0.0254;
+
+/// The names of the models available through [`TargetModel::builtin`].
+pub const BUILTIN_MODELS: &[&str] = &[
+    "deep_space_2019",
+    "infinite_recharge_2020",
+    "rapid_react_2022",
+    "apriltag_36h11_6in",
+];
+
+/// The ways in which a target looks the same after being transformed, which
+/// leads to ambiguous pose solutions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Symmetry {
+    #[default]
+    None,
+    /// The target is mirrored across its vertical center line.
+    Mirror,
+    /// The target looks the same after rotating by a multiple of `1 / n` of a
+    /// turn about its vertical axis.
+    Vertical(u32),
+    /// The target looks the same after rotating by a multiple of `1 / n` of a
+    /// turn about its face normal.
+    Normal(u32),
+}
+
+/// A single piece of a target.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TargetPiece {
+    /// The piece's corners in the model frame.
+    pub corners: Vec<Vector3>,
+    /// The width and height of the tape making up the piece, if it is a strip
+    /// or outline of tape.
+    #[serde(default)]
+    pub tape_size: Option<(f64, f64)>,
+}
+
+/// The geometry of a vision target.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TargetModel {
+    pub name: String,
+    /// The height of the model frame's origin above the floor, in meters.
+    pub height: f64,
+    pub pieces: Vec<TargetPiece>,
+    #[serde(default)]
+    pub symmetry: Symmetry,
+}
+
+impl TargetModel {
+    /// Parses a model from its JSON representation.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let model: Self = serde_json::from_str(json).context("parsing target model")?;
+        model.validate()?;
+
+        Ok(model)
+    }
+
+    /// Loads a model from a JSON file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("reading target model file {path:?}"))?;
+
+        Self::from_json(&json).with_context(|| format!("loading target model {path:?}"))
+    }
+
+    /// Returns the built-in model with the given name, if there is one.
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "deep_space_2019" => Some(deep_space_2019()),
+            "infinite_recharge_2020" => Some(infinite_recharge_2020()),
+            "rapid_react_2022" => Some(rapid_react_2022()),
+            "apriltag_36h11_6in" => Some(square_tag("apriltag_36h11_6in", 6. * INCH)),
+            _ => None,
+        }
+    }
+
+    /// Checks that every piece has enough corners to be matched.
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            !self.pieces.is_empty(),
+            "target model {} has no pieces",
+            self.name
+        );
+
+        for (idx, piece) in self.pieces.iter().enumerate() {
+            ensure!(
+                piece.corners.len() >= 3,
+                "piece {idx} of target model {} has fewer than 3 corners",
+                self.name
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns every corner of every piece, in order.
+    pub fn points(&self) -> impl Iterator<Item = Vector3> + '_ {
+        self.pieces
+            .iter()
+            .flat_map(|piece| piece.corners.iter().copied())
+    }
+
+    /// Returns the total number of corners in the model.
+    pub fn point_count(&self) -> usize {
+        self.pieces.iter().map(|piece| piece.corners.len()).sum()
+    }
+
+    /// Returns whether every corner lies in the model's `z = 0` plane.
+    pub fn is_planar(&self) -> bool {
+        self.points().all(|point| point[2].abs() < 1e-9)
+    }
+}
+
+/// A reference to a target model, either by name or defined inline.
+///
+/// A name refers to a built-in model if one matches, or otherwise to the path
+/// of a JSON model file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ModelRef {
+    Name(String),
+    Inline(TargetModel),
+}
+
+impl ModelRef {
+    pub fn resolve(&self) -> Result<TargetModel> {
+        match self {
+            ModelRef::Name(name) => match TargetModel::builtin(name) {
+                Some(model) => Ok(model),
+                None => TargetModel::load(name),
+            },
+            ModelRef::Inline(model) => {
+                model.validate()?;
+                Ok(model.clone())
+            }
+        }
+    }
+}
+
+/// Returns the corners of a `width` by `height` rectangle centered on
+/// `center` and rotated counter-clockwise by `angle` radians.
+fn rectangle(center: (f64, f64), width: f64, height: f64, angle: f64) -> Vec<Vector3> {
+    let (sin, cos) = angle.sin_cos();
+    let (hw, hh) = (width / 2., height / 2.);
+
+    [(-hw, hh), (hw, hh), (hw, -hh), (-hw, -hh)]
+        .iter()
+        .map(|&(x, y)| {
+            [
+                center.0 + x * cos - y * sin,
+                center.1 + x * sin + y * cos,
+                0.,
+            ]
+        })
+        .collect()
+}
+
+/// The pair of tilted strips marking each hatch and port in 2019. The strips
+/// are 2 by 5.5 inches, tilted 14.5 degrees towards each other and 8 inches
+/// apart at their closest point.
+fn deep_space_2019() -> TargetModel {
+    let (width, height) = (2. * INCH, 5.5 * INCH);
+    let tilt = 14.5f64.to_radians();
+
+    // Place the right strip so that its innermost corner is 4 inches right of
+    // center, and mirror it to form the left strip.
+    let right = rectangle((0., 0.), width, height, tilt);
+    let inner = right.iter().map(|c| c[0]).fold(f64::INFINITY, f64::min);
+    let offset = 4. * INCH - inner;
+
+    let right = rectangle((offset, 0.), width, height, tilt);
+    let left = rectangle((-offset, 0.), width, height, -tilt);
+
+    TargetModel {
+        name: "deep_space_2019".to_owned(),
+        height: 28.75 * INCH,
+        pieces: vec![
+            TargetPiece {
+                corners: left,
+                tape_size: Some((width, height)),
+            },
+            TargetPiece {
+                corners: right,
+                tape_size: Some((width, height)),
+            },
+        ],
+        symmetry: Symmetry::Mirror,
+    }
+}
+
+/// The outer corners of the half-hexagon outline around the 2020 power port,
+/// which is 39.25 inches wide at the top and 17 inches tall.
+fn infinite_recharge_2020() -> TargetModel {
+    let (top, height) = (39.25 * INCH, 17. * INCH);
+    let bottom = top / 2.;
+
+    TargetModel {
+        name: "infinite_recharge_2020".to_owned(),
+        height: 98.25 * INCH,
+        pieces: vec![TargetPiece {
+            corners: vec![
+                [-top / 2., height / 2., 0.],
+                [top / 2., height / 2., 0.],
+                [bottom / 2., -height / 2., 0.],
+                [-bottom / 2., -height / 2., 0.],
+            ],
+            tape_size: Some((2. * INCH, height)),
+        }],
+        symmetry: Symmetry::Mirror,
+    }
+}
+
+/// The five strips of the 2022 upper hub ring which face the viewer. The
+/// strips are 5 by 2 inches, spaced every 22.5 degrees around a ring 53.375
+/// inches across. The model frame's origin is on the face of the middle strip.
+fn rapid_react_2022() -> TargetModel {
+    let radius = 53.375 / 2. * INCH;
+    let (width, height) = (5. * INCH, 2. * INCH);
+    let half_angle = width / 2. / radius;
+
+    let pieces = (-2..=2)
+        .map(|idx| {
+            let center = idx as f64 * PI / 8.;
+            let corner =
+                |angle: f64, y: f64| [radius * angle.sin(), y, radius * angle.cos() - radius];
+
+            TargetPiece {
+                corners: vec![
+                    corner(center - half_angle, height / 2.),
+                    corner(center + half_angle, height / 2.),
+                    corner(center + half_angle, -height / 2.),
+                    corner(center - half_angle, -height / 2.),
+                ],
+                tape_size: Some((width, height)),
+            }
+        })
+        .collect();
+
+    TargetModel {
+        name: "rapid_react_2022".to_owned(),
+        height: 103. * INCH,
+        pieces,
+        symmetry: Symmetry::Vertical(16),
+    }
+}
+
+/// A square fiducial tag with the given side length. Tags are placed by a
+/// field map, so the model's height is zero.
+fn square_tag(name: &str, size: f64) -> TargetModel {
+    TargetModel {
+        name: name.to_owned(),
+        height: 0.,
+        pieces: vec![TargetPiece {
+            corners: rectangle((0., 0.), size, size, 0.),
+            tape_size: None,
+        }],
+        symmetry: Symmetry::None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_models() {
+        for name in BUILTIN_MODELS {
+            let model = TargetModel::builtin(name).unwrap();
+            model.validate().unwrap();
+
+            let json = serde_json::to_string(&model).unwrap();
+            let parsed = TargetModel::from_json(&json).unwrap();
+            assert_eq!(parsed.name, model.name);
+            assert_eq!(parsed.symmetry, model.symmetry);
+
+            for (a, b) in parsed.points().zip(model.points()) {
+                assert!((0..3).all(|idx| (a[idx] - b[idx]).abs() < 1e-12));
+            }
+        }
+
+        let deep_space = TargetModel::builtin("deep_space_2019").unwrap();
+        let left_inner = deep_space.pieces[0].corners[1][0];
+        assert!((left_inner + 4. * INCH).abs() < 1e-9);
+        assert!(deep_space.is_planar());
+        assert!(!TargetModel::builtin("rapid_react_2022")
+            .unwrap()
+            .is_planar());
+    }
+}
@@ -0,0 +1,176 @@
+//! A history of the robot's pose on the field, for placing targets seen in
+//! past frames onto the field.
+//!
+//! Poses are recorded against the same clock as [`VisionTarget::capture_time`]:
+//! microseconds since the Unix epoch. The field frame has `x` and `y` on the
+//! floor and `z` up, and a robot's heading is the direction of its forward axis
+//! measured counter-clockwise from the field's `x` axis.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    transform::{normalize_angle, Vector3},
+    types::{CameraConfig, VisionTarget},
+};
+
+/// The position and heading of the robot on the field.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FieldPose {
+    pub x: f64,
+    pub y: f64,
+    /// The heading in radians, positive counter-clockwise.
+    pub heading: f64,
+}
+
+impl FieldPose {
+    pub fn new(x: f64, y: f64, heading: f64) -> Self {
+        Self { x, y, heading }
+    }
+
+    /// Returns the pose a fraction `t` of the way from `self` to `other`,
+    /// turning through the smaller angle between their headings.
+    pub fn interpolate(&self, other: &FieldPose, t: f64) -> FieldPose {
+        FieldPose {
+            x: self.x + (other.x - self.x) * t,
+            y: self.y + (other.y - self.y) * t,
+            heading: normalize_angle(
+                self.heading + normalize_angle(other.heading - self.heading) * t,
+            ),
+        }
+    }
+
+    /// Maps a point in the robot frame onto the field.
+    pub fn to_field(&self, [x, y, z]: Vector3) -> Vector3 {
+        let (sin, cos) = self.heading.sin_cos();
+
+        [self.x + x * cos - y * sin, self.y + x * sin + y * cos, z]
+    }
+
+    /// Maps a point on the field into the robot frame.
+    pub fn from_field(&self, [x, y, z]: Vector3) -> Vector3 {
+        let (sin, cos) = self.heading.sin_cos();
+        let (dx, dy) = (x - self.x, y - self.y);
+
+        [dx * cos + dy * sin, -dx * sin + dy * cos, z]
+    }
+}
+
+/// A bounded, time-ordered buffer of the robot's recent poses.
+#[derive(Clone, Debug)]
+pub struct OdometryHistory {
+    capacity: usize,
+    samples: VecDeque<(u64, FieldPose)>,
+}
+
+impl OdometryHistory {
+    /// Creates a history which keeps at most `capacity` poses.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Records the robot's pose at `time`, in microseconds since the Unix
+    /// epoch. Poses may arrive out of order; the oldest are dropped once the
+    /// history is full.
+    pub fn record(&mut self, time: u64, pose: FieldPose) {
+        let idx = self.samples.partition_point(|&(sample, _)| sample <= time);
+
+        if idx > 0 && self.samples[idx - 1].0 == time {
+            self.samples[idx - 1].1 = pose;
+        } else {
+            self.samples.insert(idx, (time, pose));
+        }
+
+        while self.samples.len() > self.capacity {
+            self.samples.pop_front();
+        }
+    }
+
+    /// The most recently recorded pose and its time.
+    pub fn latest(&self) -> Option<(u64, FieldPose)> {
+        self.samples.back().copied()
+    }
+
+    /// Returns the pose at `time`, interpolating between the recorded poses on
+    /// either side. Returns `None` if `time` is outside the recorded span.
+    pub fn at(&self, time: u64) -> Option<FieldPose> {
+        let idx = self.samples.partition_point(|&(sample, _)| sample < time);
+        let (after_time, after) = *self.samples.get(idx)?;
+
+        if after_time == time {
+            return Some(after);
+        }
+
+        let (before_time, before) = *self.samples.get(idx.checked_sub(1)?)?;
+        let t = (time - before_time) as f64 / (after_time - before_time) as f64;
+
+        Some(before.interpolate(&after, t))
+    }
+
+    /// Returns the field position of a target measured relative to `camera`,
+    /// using the robot's pose when the target's frame was captured.
+    pub fn field_position(&self, target: &VisionTarget, camera: &CameraConfig) -> Option<Vector3> {
+        let pose = self.at(target.capture_time)?;
+
+        Some(pose.to_field(target.to_robot_frame(camera).position()))
+    }
+
+    /// Removes every recorded pose.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_PI_2, PI};
+
+    use super::*;
+
+    #[test]
+    fn test_odometry_history() {
+        let mut history = OdometryHistory::new(3);
+        history.record(2_000, FieldPose::new(2., 0., PI - 0.1));
+        history.record(0, FieldPose::new(0., 0., 0.));
+        history.record(1_000, FieldPose::new(1., 1., FRAC_PI_2));
+        history.record(3_000, FieldPose::new(2., 2., -PI + 0.1));
+
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.at(500), None);
+        assert_eq!(history.at(4_000), None);
+
+        let pose = history.at(1_500).unwrap();
+        assert!((pose.x - 1.5).abs() < 1e-12);
+        assert!((pose.y - 0.5).abs() < 1e-12);
+
+        // The heading turns through the wraparound rather than back around.
+        let pose = history.at(2_500).unwrap();
+        assert!((pose.heading.abs() - PI).abs() < 1e-9);
+
+        let target = VisionTarget {
+            dist: 1.,
+            height: 0.5,
+            capture_time: 1_000,
+            ..VisionTarget::default()
+        };
+        let [x, y, z] = history
+            .field_position(&target, &CameraConfig::default())
+            .unwrap();
+
+        assert!((x - 1.).abs() < 1e-9);
+        assert!((y - 2.).abs() < 1e-9);
+        assert!((z - 0.5).abs() < 1e-9);
+    }
+}
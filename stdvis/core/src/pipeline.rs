@@ -0,0 +1,592 @@
+//! A vision pipeline connecting a camera to the targets it sees.
+//!
+//! Each frame runs through the stages grab, preprocess, extract, filter,
+//! group, analyze, inspect and post-process, in that order. Every stage but
+//! the camera is boxed, so it may be swapped between frames without
+//! rebuilding the pipeline. Pipelines may also be defined in JSON or TOML
+//! files, whose stages are created by name from a [`StageRegistry`].
+
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Error, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, debug_span, field, warn};
+
+use crate::{
+    filter::{FilterChain, Rejection},
+    traits::{Camera, ContourAnalyzer, ContourExtractor, ContourGrouper, ImageData},
+    transform::Transform,
+    types::{CameraConfig, ContourGroup, Image, VisionTarget},
+};
+
+mod definition;
+mod registry;
+mod reload;
+
+pub use self::{
+    definition::{PipelineDefinition, StageDefinition},
+    registry::StageRegistry,
+    reload::{FileWatcher, PipelineReloader},
+};
+
+/// A stage that modifies each image before contours are extracted from it,
+/// such as by blurring or masking it.
+pub trait Preprocessor<I: ImageData> {
+    fn process(&self, image: &mut Image<I>) -> Result<()>;
+}
+
+impl<I: ImageData, F: Fn(&mut Image<I>) -> Result<()>> Preprocessor<I> for F {
+    fn process(&self, image: &mut Image<I>) -> Result<()> {
+        self(image)
+    }
+}
+
+/// A `ContourExtractor` for a single kind of image storage, which unlike
+/// `ContourExtractor` may be used as a trait object.
+pub trait ExtractStage<I: ImageData> {
+    fn extract<'src>(&'src self, image: &Image<'src, I>) -> Result<Vec<ContourGroup<'src>>>;
+}
+
+impl<I: ImageData, E: ContourExtractor> ExtractStage<I> for E {
+    fn extract<'src>(&'src self, image: &Image<'src, I>) -> Result<Vec<ContourGroup<'src>>> {
+        self.extract_from(image)
+    }
+}
+
+/// A stage that modifies the targets found in each frame, such as by moving
+/// them into the robot frame or smoothing them over time, or that publishes
+/// them as an output.
+pub trait PostProcessor {
+    fn process(
+        &mut self,
+        camera: &CameraConfig,
+        targets: Vec<VisionTarget>,
+    ) -> Result<Vec<VisionTarget>>;
+}
+
+/// A stage that sees each frame's image along with the groups found in it and
+/// the targets measured from them, such as to draw debug overlays.
+///
+/// Inspectors run after analysis, so targets are still relative to the
+/// camera. Each target's entry in `poses` holds the pose of its model in the
+/// camera frame, if the analyzer solved for one. An inspector which fails is
+/// logged, and the frame carries on without it.
+pub trait Inspector<I: ImageData> {
+    fn inspect(
+        &mut self,
+        image: &Image<I>,
+        groups: &[ContourGroup],
+        targets: &[VisionTarget],
+        poses: &[Option<Transform>],
+    ) -> Result<()>;
+}
+
+/// A `PostProcessor` which makes targets relative to the robot rather than
+/// the camera.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RobotFrame;
+
+impl PostProcessor for RobotFrame {
+    fn process(
+        &mut self,
+        camera: &CameraConfig,
+        targets: Vec<VisionTarget>,
+    ) -> Result<Vec<VisionTarget>> {
+        Ok(targets
+            .iter()
+            .map(|target| target.to_robot_frame(camera))
+            .collect())
+    }
+}
+
+/// Parameters for a `JsonOutput`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct JsonOutputConfig {
+    /// A file to append targets to. If omitted, they are written to standard
+    /// output.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+/// A `PostProcessor` which writes each frame's targets as one line of JSON,
+/// and passes them on unchanged.
+pub struct JsonOutput {
+    writer: Box<dyn Write>,
+}
+
+impl JsonOutput {
+    pub fn new(config: JsonOutputConfig) -> Result<Self> {
+        let writer: Box<dyn Write> = match config.path {
+            Some(path) => Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .with_context(|| format!("opening target output {path:?}"))?,
+            ),
+            None => Box::new(io::stdout()),
+        };
+
+        Ok(Self { writer })
+    }
+}
+
+impl PostProcessor for JsonOutput {
+    fn process(
+        &mut self,
+        _camera: &CameraConfig,
+        targets: Vec<VisionTarget>,
+    ) -> Result<Vec<VisionTarget>> {
+        serde_json::to_writer(&mut self.writer, &targets)?;
+        writeln!(self.writer)?;
+        self.writer.flush()?;
+
+        Ok(targets)
+    }
+}
+
+/// The time spent in each stage of a frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StageTimings {
+    pub grab: Duration,
+    pub preprocess: Duration,
+    pub extract: Duration,
+    pub filter: Duration,
+    pub group: Duration,
+    pub analyze: Duration,
+    pub inspect: Duration,
+    pub postprocess: Duration,
+    pub output: Duration,
+}
+
+impl StageTimings {
+    /// The time spent in every stage.
+    pub fn total(&self) -> Duration {
+        self.grab
+            + self.preprocess
+            + self.extract
+            + self.filter
+            + self.group
+            + self.analyze
+            + self.inspect
+            + self.postprocess
+            + self.output
+    }
+}
+
+/// The results of running one frame through a `Pipeline`.
+#[derive(Debug)]
+pub struct Frame {
+    /// The sequence number of the frame.
+    pub sequence: u32,
+    pub targets: Vec<VisionTarget>,
+    /// The contours dropped by the pipeline's filter.
+    pub rejections: Vec<Rejection>,
+    /// The groups which could not be analyzed, by id, with the reason.
+    pub failures: Vec<(u16, Error)>,
+    pub timings: StageTimings,
+    /// The time from the frame's capture until its targets were output.
+    pub latency: Duration,
+}
+
+/// Runs the images from a camera through a series of stages to find targets.
+///
+/// Once post-processed, targets are stamped with the frame they were seen
+/// in, when it was captured, and how long they took to process.
+pub struct Pipeline<C: Camera> {
+    camera: C,
+    preprocessors: Vec<Box<dyn Preprocessor<C::ImageStorage>>>,
+    extractor: Box<dyn ExtractStage<C::ImageStorage>>,
+    filter: FilterChain,
+    grouper: Option<Box<dyn ContourGrouper>>,
+    analyzer: Box<dyn ContourAnalyzer>,
+    inspectors: Vec<Box<dyn Inspector<C::ImageStorage>>>,
+    postprocessors: Vec<Box<dyn PostProcessor>>,
+    outputs: Vec<Box<dyn PostProcessor>>,
+}
+
+impl<C: Camera> Pipeline<C> {
+    /// Creates a pipeline with no preprocessing, filtering, regrouping,
+    /// inspection, post-processing or outputs.
+    pub fn new(
+        camera: C,
+        extractor: Box<dyn ExtractStage<C::ImageStorage>>,
+        analyzer: Box<dyn ContourAnalyzer>,
+    ) -> Self {
+        Self {
+            camera,
+            preprocessors: Vec::new(),
+            extractor,
+            filter: FilterChain::default(),
+            grouper: None,
+            analyzer,
+            inspectors: Vec::new(),
+            postprocessors: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn camera(&self) -> &C {
+        &self.camera
+    }
+
+    pub fn camera_mut(&mut self) -> &mut C {
+        &mut self.camera
+    }
+
+    /// Consumes the pipeline, returning its camera.
+    pub fn into_camera(self) -> C {
+        self.camera
+    }
+
+    /// The preprocessors, which are run in order.
+    pub fn preprocessors_mut(&mut self) -> &mut Vec<Box<dyn Preprocessor<C::ImageStorage>>> {
+        &mut self.preprocessors
+    }
+
+    pub fn set_extractor(&mut self, extractor: Box<dyn ExtractStage<C::ImageStorage>>) {
+        self.extractor = extractor;
+    }
+
+    pub fn filter(&self) -> &FilterChain {
+        &self.filter
+    }
+
+    pub fn set_filter(&mut self, filter: FilterChain) {
+        self.filter = filter;
+    }
+
+    /// Sets the grouper used to regroup the contours left after filtering. If
+    /// there is none, the extractor's groups are kept.
+    pub fn set_grouper(&mut self, grouper: Option<Box<dyn ContourGrouper>>) {
+        self.grouper = grouper;
+    }
+
+    pub fn set_analyzer(&mut self, analyzer: Box<dyn ContourAnalyzer>) {
+        self.analyzer = analyzer;
+    }
+
+    /// The inspectors, which are run in order.
+    pub fn inspectors_mut(&mut self) -> &mut Vec<Box<dyn Inspector<C::ImageStorage>>> {
+        &mut self.inspectors
+    }
+
+    /// The post-processors, which are run in order.
+    pub fn postprocessors_mut(&mut self) -> &mut Vec<Box<dyn PostProcessor>> {
+        &mut self.postprocessors
+    }
+
+    /// The outputs, which are run in order once targets have been stamped.
+    pub fn outputs_mut(&mut self) -> &mut Vec<Box<dyn PostProcessor>> {
+        &mut self.outputs
+    }
+
+    /// Grabs the next frame from the camera and finds the targets in it.
+    ///
+    /// A group which fails analysis is recorded in the frame's `failures`
+    /// rather than failing the whole frame, and a failing inspector is only
+    /// logged. Each frame is traced in a `frame`
+    /// span holding the camera's id and the frame's sequence number.
+    pub fn run_frame(&mut self) -> Result<Frame> {
+        let span = debug_span!(
+            "frame",
+            camera = self.camera.config().id,
+            sequence = field::Empty
+        );
+        let _entered = span.enter();
+
+        let mut timings = StageTimings::default();
+
+        let start = Instant::now();
+        let mut image = self.camera.grab_frame().context("grabbing frame")?;
+        span.record("sequence", image.sequence);
+        let mut mark = Instant::now();
+        timings.grab = mark - start;
+
+        let mut lap = || {
+            let now = Instant::now();
+            let elapsed = now - mark;
+            mark = now;
+            elapsed
+        };
+
+        for preprocessor in &self.preprocessors {
+            preprocessor
+                .process(&mut image)
+                .context("preprocessing frame")?;
+        }
+        timings.preprocess = lap();
+
+        let mut groups = self
+            .extractor
+            .extract(&image)
+            .context("extracting contours")?;
+        timings.extract = lap();
+
+        let rejections = self.filter.apply(&mut groups);
+        timings.filter = lap();
+
+        if let Some(grouper) = &self.grouper {
+            groups = grouper.regroup(groups);
+        }
+        timings.group = lap();
+
+        let mut targets = Vec::new();
+        let mut poses = Vec::new();
+        let mut failures = Vec::new();
+
+        for group in &groups {
+            match self.analyzer.analyze_with_pose(group) {
+                Ok((target, pose)) => {
+                    targets.push(target);
+                    poses.push(pose);
+                }
+                Err(error) => {
+                    debug!(group = group.id, "analysis failed: {error:#}");
+                    failures.push((group.id, error));
+                }
+            }
+        }
+        timings.analyze = lap();
+
+        for inspector in &mut self.inspectors {
+            if let Err(error) = inspector.inspect(&image, &groups, &targets, &poses) {
+                warn!("inspection failed: {error:#}");
+            }
+        }
+        timings.inspect = lap();
+
+        for postprocessor in &mut self.postprocessors {
+            targets = postprocessor
+                .process(image.camera, targets)
+                .context("post-processing targets")?;
+        }
+
+        let processed = Instant::now();
+        for target in &mut targets {
+            target.stamp(&image, processed);
+        }
+        timings.postprocess = lap();
+
+        for output in &mut self.outputs {
+            targets = output
+                .process(image.camera, targets)
+                .context("outputting targets")?;
+        }
+        timings.output = lap();
+
+        debug!(
+            targets = targets.len(),
+            rejections = rejections.len(),
+            failures = failures.len(),
+            total_us = timings.total().as_micros() as u64,
+            "processed frame"
+        );
+
+        Ok(Frame {
+            sequence: image.sequence,
+            targets,
+            rejections,
+            failures,
+            timings,
+            latency: image.timestamp.elapsed(),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io;
+
+    use anyhow::bail;
+    use ndarray::{Array3, ArrayViewD, ArrayViewMutD};
+
+    use super::*;
+    use crate::types::Contour;
+
+    pub(crate) struct ArrayImage(Array3<u8>);
+
+    impl ImageData for ArrayImage {
+        type Inner = Array3<u8>;
+
+        fn as_pixels(&self) -> ArrayViewD<'_, u8> {
+            self.0.view().into_dyn()
+        }
+
+        fn as_pixels_mut(&mut self) -> ArrayViewMutD<'_, u8> {
+            self.0.view_mut().into_dyn()
+        }
+
+        fn as_raw(&self) -> &Self::Inner {
+            &self.0
+        }
+
+        fn as_raw_mut(&mut self) -> &mut Self::Inner {
+            &mut self.0
+        }
+    }
+
+    pub(crate) struct TestCamera {
+        pub config: CameraConfig,
+        pub frames: u32,
+    }
+
+    impl Camera for TestCamera {
+        type ImageStorage = ArrayImage;
+
+        fn config(&self) -> &CameraConfig {
+            &self.config
+        }
+
+        fn grab_frame(&mut self) -> io::Result<Image<'_, ArrayImage>> {
+            self.frames += 1;
+
+            Ok(Image::new(
+                Instant::now(),
+                self.frames,
+                &self.config,
+                ArrayImage(Array3::zeros((4, 4, 1))),
+            ))
+        }
+    }
+
+    /// Finds one single-point group for each bright pixel.
+    pub(crate) struct PixelExtractor;
+
+    impl ContourExtractor for PixelExtractor {
+        fn extract_from<'src, I: ImageData>(
+            &'src self,
+            image: &Image<'src, I>,
+        ) -> Result<Vec<ContourGroup<'src>>> {
+            Ok(image
+                .as_pixels()
+                .indexed_iter()
+                .filter(|(_, &value)| value > 0)
+                .enumerate()
+                .map(|(id, (idx, _))| ContourGroup {
+                    id: id as u16,
+                    camera: image.camera,
+                    contours: vec![Contour::new(vec![(idx[1] as f32, idx[0] as f32)])],
+                    fiducial: None,
+                })
+                .collect())
+        }
+    }
+
+    /// Measures the distance of a group as its pixel's column, failing for
+    /// the first column.
+    pub(crate) struct ColumnAnalyzer;
+
+    impl ContourAnalyzer for ColumnAnalyzer {
+        fn analyze(&self, group: &ContourGroup) -> Result<VisionTarget> {
+            let (x, _) = group.contours[0].points[0];
+
+            if x == 0. {
+                bail!("target in first column");
+            }
+
+            Ok(VisionTarget {
+                id: group.id,
+                dist: x as f64,
+                ..VisionTarget::default()
+            })
+        }
+    }
+
+    struct FailingInspector;
+
+    impl Inspector<ArrayImage> for FailingInspector {
+        fn inspect(
+            &mut self,
+            _image: &Image<ArrayImage>,
+            _groups: &[ContourGroup],
+            _targets: &[VisionTarget],
+            _poses: &[Option<Transform>],
+        ) -> Result<()> {
+            bail!("failed to inspect frame")
+        }
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let camera = TestCamera {
+            config: CameraConfig::default(),
+            frames: 0,
+        };
+        let mut pipeline =
+            Pipeline::new(camera, Box::new(PixelExtractor), Box::new(ColumnAnalyzer));
+
+        let frame = pipeline.run_frame().unwrap();
+        assert_eq!(frame.sequence, 1);
+        assert!(frame.targets.is_empty());
+
+        pipeline
+            .preprocessors_mut()
+            .push(Box::new(|image: &mut Image<ArrayImage>| {
+                let mut pixels = image.as_pixels_mut();
+                pixels[[0, 0, 0]] = 255;
+                pixels[[1, 2, 0]] = 255;
+                Ok(())
+            }));
+
+        let frame = pipeline.run_frame().unwrap();
+        assert_eq!(frame.sequence, 2);
+        assert_eq!(frame.failures.len(), 1);
+        assert_eq!(frame.targets.len(), 1);
+        assert_eq!(frame.targets[0].dist, 2.);
+        assert_eq!(frame.targets[0].frame, 2);
+        assert!(frame.targets[0].capture_time > 0);
+        assert!(frame.timings.total() >= frame.timings.preprocess);
+
+        // Swapping the post-processors changes the next frame's targets.
+        let mut camera_pose = CameraConfig::default();
+        camera_pose.pose.dist = 1.;
+        pipeline.camera_mut().config = camera_pose;
+        pipeline.postprocessors_mut().push(Box::new(RobotFrame));
+
+        let frame = pipeline.run_frame().unwrap();
+        assert_eq!(frame.targets[0].dist, 3.);
+
+        // A failing inspector doesn't cost the frame its targets.
+        pipeline.inspectors_mut().push(Box::new(FailingInspector));
+
+        let frame = pipeline.run_frame().unwrap();
+        assert_eq!(frame.targets.len(), 1);
+    }
+
+    #[test]
+    fn test_json_output() {
+        let path = std::env::temp_dir().join(format!("stdvis-output-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut output = JsonOutput::new(JsonOutputConfig {
+            path: Some(path.clone()),
+        })
+        .unwrap();
+
+        let camera = CameraConfig::default();
+        let target = VisionTarget {
+            dist: 2.,
+            ..VisionTarget::default()
+        };
+
+        let targets = output.process(&camera, vec![target.clone()]).unwrap();
+        assert_eq!(targets.len(), 1);
+        output.process(&camera, Vec::new()).unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        let lines = written.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+
+        let parsed: Vec<VisionTarget> = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(parsed[0].dist, target.dist);
+        assert_eq!(lines[1], "[]");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
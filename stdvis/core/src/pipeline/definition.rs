@@ -0,0 +1,294 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{ensure, Context, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+use crate::{filter::FilterChain, grouping::GroupingStrategy, types::CameraConfig};
+
+/// A stage of a pipeline, named by the type it was registered under in a
+/// [`StageRegistry`](super::StageRegistry).
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct StageDefinition {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The stage's config. Omitted params are treated as an empty table, so
+    /// that every field takes its default.
+    #[serde(default)]
+    pub params: Value,
+    /// A separate JSON or TOML file to read the stage's params from, such as
+    /// a file of thresholds tuned on their own. Relative paths are resolved
+    /// from the directory of the pipeline definition.
+    #[serde(default)]
+    pub params_file: Option<PathBuf>,
+}
+
+/// A stage as it is written out. Params read from a params file are left to
+/// the file, so that the output parses again.
+#[derive(Serialize)]
+struct WrittenStage<'a> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+    #[serde(skip_serializing_if = "Value::is_null")]
+    params: &'a Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    params_file: Option<&'a Path>,
+}
+
+impl Serialize for StageDefinition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let params = match self.params_file {
+            Some(_) => &Value::Null,
+            None => &self.params,
+        };
+
+        WrittenStage {
+            kind: &self.kind,
+            params,
+            params_file: self.params_file.as_deref(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl StageDefinition {
+    /// Parses the stage's params as its registered factory does, for code
+    /// which creates a stage of a known type itself.
+    pub fn parse_params<C: DeserializeOwned>(&self) -> Result<C> {
+        parse(&self.params)
+    }
+
+    /// Reads the stage's params from its params file, if it has one, with a
+    /// relative path resolved from `base`.
+    fn load_params(&mut self, base: &Path) -> Result<()> {
+        let path = match &self.params_file {
+            Some(path) => base.join(path),
+            None => return Ok(()),
+        };
+
+        ensure!(
+            self.params.is_null(),
+            "stage {:?} has both params and a params file",
+            self.kind
+        );
+
+        self.params = read(&path)
+            .with_context(|| format!("loading params of stage {:?} from {path:?}", self.kind))?;
+        self.params_file = Some(path);
+
+        Ok(())
+    }
+}
+
+/// A complete pipeline, as written in a JSON or TOML file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PipelineDefinition {
+    pub name: String,
+    pub camera: CameraConfig,
+    #[serde(default)]
+    pub preprocess: Vec<StageDefinition>,
+    pub extractor: StageDefinition,
+    #[serde(default)]
+    pub filter: FilterChain,
+    /// The strategy used to regroup contours after filtering. If omitted, the
+    /// extractor's groups are kept.
+    #[serde(default)]
+    pub grouping: Option<GroupingStrategy>,
+    pub analyzer: StageDefinition,
+    #[serde(default)]
+    pub postprocess: Vec<StageDefinition>,
+    /// Post-processors which publish the targets, run after `postprocess`.
+    #[serde(default)]
+    pub outputs: Vec<StageDefinition>,
+}
+
+impl PipelineDefinition {
+    /// Parses a pipeline definition from JSON, resolving params files from the
+    /// current directory.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let value = serde_json::from_str(json).context("parsing pipeline definition")?;
+        Self::from_value(value, Path::new(""))
+    }
+
+    /// Parses a pipeline definition from TOML, resolving params files from the
+    /// current directory.
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        let value = toml::from_str(toml).context("parsing pipeline definition")?;
+        Self::from_value(value, Path::new(""))
+    }
+
+    /// Loads a pipeline definition from a file, which is read as TOML if it
+    /// has a `.toml` extension and as JSON otherwise.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+
+        read(path)
+            .and_then(|value| Self::from_value(value, base))
+            .with_context(|| format!("loading pipeline definition {path:?}"))
+    }
+
+    /// Returns every stage in the order it runs.
+    pub fn stages(&self) -> impl Iterator<Item = &StageDefinition> {
+        self.preprocess
+            .iter()
+            .chain([&self.extractor, &self.analyzer])
+            .chain(&self.postprocess)
+            .chain(&self.outputs)
+    }
+
+    /// Returns the params files read by the definition's stages.
+    pub fn params_files(&self) -> impl Iterator<Item = &Path> {
+        self.stages()
+            .filter_map(|stage| stage.params_file.as_deref())
+    }
+
+    fn from_value(value: Value, base: &Path) -> Result<Self> {
+        let mut definition: Self =
+            serde_json::from_value(value).context("parsing pipeline definition")?;
+
+        let stages = definition
+            .preprocess
+            .iter_mut()
+            .chain([&mut definition.extractor, &mut definition.analyzer])
+            .chain(&mut definition.postprocess)
+            .chain(&mut definition.outputs);
+
+        for stage in stages {
+            stage.load_params(base)?;
+        }
+
+        Ok(definition)
+    }
+}
+
+/// Parses a stage's params, treating missing params as an empty table.
+pub(super) fn parse<C: DeserializeOwned>(params: &Value) -> Result<C> {
+    let result = match params {
+        Value::Null => C::deserialize(&Value::Object(Map::new())),
+        params => C::deserialize(params),
+    };
+
+    result.context("parsing stage params")
+}
+
+/// Reads a JSON or TOML file, by its extension.
+fn read(path: &Path) -> Result<Value> {
+    let contents = fs::read_to_string(path).with_context(|| format!("reading {path:?}"))?;
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&contents).with_context(|| format!("parsing {path:?}")),
+        _ => serde_json::from_str(&contents).with_context(|| format!("parsing {path:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterRule;
+
+    #[test]
+    fn test_definition_formats() {
+        let toml = PipelineDefinition::from_toml(
+            r#"
+            name = "test"
+
+            [camera]
+            id = 1
+            resolution = [640, 480]
+            fov = [1.0, 0.75]
+
+            [camera.pose]
+            angle = 0.0
+            dist = 0.0
+            height = 0.5
+            yaw = 0.0
+            pitch = 0.0
+            roll = 0.0
+
+            [camera.intrinsic_matrix]
+            v = 1
+            dim = [3, 3]
+            data = [500.0, 0.0, 320.0, 0.0, 500.0, 240.0, 0.0, 0.0, 1.0]
+
+            [camera.distortion_coeffs]
+            v = 1
+            dim = [5]
+            data = [0.0, 0.0, 0.0, 0.0, 0.0]
+
+            [extractor]
+            type = "hsv_threshold"
+            params = { lower = [50, 100, 100], upper = [70, 255, 255] }
+
+            [filter]
+            rules = [{ Area = { min = 100.0 } }]
+
+            [analyzer]
+            type = "pinhole"
+
+            [[outputs]]
+            type = "json"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(toml.camera.id, 1);
+        assert_eq!(toml.extractor.kind, "hsv_threshold");
+        assert_eq!(toml.extractor.params["upper"][0], 70);
+        assert_eq!(toml.analyzer.params, Value::Null);
+        assert!(toml
+            .analyzer
+            .parse_params::<Map<String, Value>>()
+            .unwrap()
+            .is_empty());
+        assert!(matches!(toml.filter.rules[0], FilterRule::Area(_)));
+        assert!(toml.grouping.is_none());
+        assert_eq!(toml.outputs.len(), 1);
+
+        let json = PipelineDefinition::from_json(&serde_json::to_string(&toml).unwrap()).unwrap();
+        assert_eq!(json.extractor, toml.extractor);
+        assert_eq!(json.filter, toml.filter);
+    }
+
+    #[test]
+    fn test_params_file_round_trip() {
+        let dir = std::env::temp_dir().join(format!("stdvis-definition-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("thresholds.toml"), "lower = [50, 100, 100]").unwrap();
+        fs::write(
+            dir.join("pipeline.json"),
+            r#"{
+                "name": "test",
+                "camera": {
+                    "id": 0,
+                    "resolution": [640, 480],
+                    "pose": { "angle": 0, "dist": 0, "height": 0, "yaw": 0, "pitch": 0, "roll": 0 },
+                    "fov": [1.0, 0.75],
+                    "intrinsic_matrix": { "v": 1, "dim": [3, 3], "data": [1, 0, 0, 0, 1, 0, 0, 0, 1] },
+                    "distortion_coeffs": { "v": 1, "dim": [0], "data": [] }
+                },
+                "extractor": { "type": "hsv_threshold", "params_file": "thresholds.toml" },
+                "analyzer": { "type": "pinhole" }
+            }"#,
+        )
+        .unwrap();
+
+        let definition = PipelineDefinition::load(dir.join("pipeline.json")).unwrap();
+        assert_eq!(definition.extractor.params["lower"][0], 50);
+
+        // The written definition refers to the params file without repeating
+        // its params, and loads back the same.
+        let written = serde_json::to_value(&definition).unwrap();
+        assert!(written["extractor"].get("params").is_none());
+        assert!(written["analyzer"].get("params").is_none());
+
+        let reparsed = PipelineDefinition::from_json(&written.to_string()).unwrap();
+        assert_eq!(reparsed.extractor, definition.extractor);
+        assert_eq!(reparsed.analyzer, definition.analyzer);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
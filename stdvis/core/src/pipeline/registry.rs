@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde_json::Value;
+use tracing::debug;
+
+use super::{
+    definition::parse, ExtractStage, JsonOutput, Pipeline, PipelineDefinition, PostProcessor,
+    Preprocessor, RobotFrame, StageDefinition,
+};
+use crate::{
+    analyzers::PinholeAnalyzer,
+    filter::FilterChain,
+    traits::{Camera, ContourAnalyzer, ContourGrouper, ImageData},
+};
+
+type Factory<T> = Box<dyn Fn(&Value) -> Result<T>>;
+
+/// The stages of a pipeline, created from a definition before any are
+/// applied, so that a definition which fails leaves the pipeline untouched.
+struct Stages<I: ImageData> {
+    preprocessors: Vec<Box<dyn Preprocessor<I>>>,
+    extractor: Box<dyn ExtractStage<I>>,
+    filter: FilterChain,
+    grouper: Option<Box<dyn ContourGrouper>>,
+    analyzer: Box<dyn ContourAnalyzer>,
+    postprocessors: Vec<Box<dyn PostProcessor>>,
+    outputs: Vec<Box<dyn PostProcessor>>,
+}
+
+/// Named constructors for pipeline stages, used to create pipelines from
+/// their definitions.
+///
+/// A new registry holds the stages provided by this crate: the `pinhole`
+/// analyzer, the `robot_frame` post-processor and the `json` output. Crates
+/// providing other stages, such as those backed by OpenCV, register them by
+/// name.
+pub struct StageRegistry<I: ImageData> {
+    preprocessors: HashMap<String, Factory<Box<dyn Preprocessor<I>>>>,
+    extractors: HashMap<String, Factory<Box<dyn ExtractStage<I>>>>,
+    analyzers: HashMap<String, Factory<Box<dyn ContourAnalyzer>>>,
+    postprocessors: HashMap<String, Factory<Box<dyn PostProcessor>>>,
+}
+
+impl<I: ImageData> StageRegistry<I> {
+    pub fn new() -> Self {
+        let mut registry = Self {
+            preprocessors: HashMap::new(),
+            extractors: HashMap::new(),
+            analyzers: HashMap::new(),
+            postprocessors: HashMap::new(),
+        };
+
+        registry.register_analyzer("pinhole", PinholeAnalyzer::new);
+        registry.register_postprocessor("robot_frame", |_: IgnoredAny| Ok(RobotFrame));
+        registry.register_postprocessor("json", JsonOutput::new);
+
+        registry
+    }
+
+    /// Registers a preprocessor, created from params of type `C`.
+    pub fn register_preprocessor<C, P>(
+        &mut self,
+        name: &str,
+        factory: impl Fn(C) -> Result<P> + 'static,
+    ) where
+        C: DeserializeOwned,
+        P: Preprocessor<I> + 'static,
+    {
+        self.preprocessors.insert(
+            name.to_owned(),
+            Box::new(move |params: &Value| {
+                Ok(Box::new(factory(parse(params)?)?) as Box<dyn Preprocessor<I>>)
+            }),
+        );
+    }
+
+    /// Registers an extractor, created from params of type `C`.
+    pub fn register_extractor<C, E>(
+        &mut self,
+        name: &str,
+        factory: impl Fn(C) -> Result<E> + 'static,
+    ) where
+        C: DeserializeOwned,
+        E: ExtractStage<I> + 'static,
+    {
+        self.extractors.insert(
+            name.to_owned(),
+            Box::new(move |params: &Value| {
+                Ok(Box::new(factory(parse(params)?)?) as Box<dyn ExtractStage<I>>)
+            }),
+        );
+    }
+
+    /// Registers an analyzer, created from params of type `C`.
+    pub fn register_analyzer<C, A>(
+        &mut self,
+        name: &str,
+        factory: impl Fn(C) -> Result<A> + 'static,
+    ) where
+        C: DeserializeOwned,
+        A: ContourAnalyzer + 'static,
+    {
+        self.analyzers.insert(
+            name.to_owned(),
+            Box::new(move |params: &Value| {
+                Ok(Box::new(factory(parse(params)?)?) as Box<dyn ContourAnalyzer>)
+            }),
+        );
+    }
+
+    /// Registers a post-processor or output, created from params of type `C`.
+    pub fn register_postprocessor<C, P>(
+        &mut self,
+        name: &str,
+        factory: impl Fn(C) -> Result<P> + 'static,
+    ) where
+        C: DeserializeOwned,
+        P: PostProcessor + 'static,
+    {
+        self.postprocessors.insert(
+            name.to_owned(),
+            Box::new(move |params: &Value| {
+                Ok(Box::new(factory(parse(params)?)?) as Box<dyn PostProcessor>)
+            }),
+        );
+    }
+
+    /// Creates a pipeline from its definition, around a camera opened with
+    /// the definition's camera config.
+    pub fn build<C: Camera<ImageStorage = I>>(
+        &self,
+        definition: &PipelineDefinition,
+        camera: C,
+    ) -> Result<Pipeline<C>> {
+        let stages = self.instantiate(definition)?;
+        let mut pipeline = Pipeline::new(camera, stages.extractor, stages.analyzer);
+
+        *pipeline.preprocessors_mut() = stages.preprocessors;
+        pipeline.set_filter(stages.filter);
+        pipeline.set_grouper(stages.grouper);
+        *pipeline.postprocessors_mut() = stages.postprocessors;
+        *pipeline.outputs_mut() = stages.outputs;
+
+        Ok(pipeline)
+    }
+
+    /// Replaces every stage of a pipeline with those of a definition, keeping
+    /// its camera. If any stage can't be created, the pipeline is left as it
+    /// was.
+    pub fn configure<C: Camera<ImageStorage = I>>(
+        &self,
+        pipeline: &mut Pipeline<C>,
+        definition: &PipelineDefinition,
+    ) -> Result<()> {
+        let stages = self.instantiate(definition)?;
+
+        *pipeline.preprocessors_mut() = stages.preprocessors;
+        pipeline.set_extractor(stages.extractor);
+        pipeline.set_filter(stages.filter);
+        pipeline.set_grouper(stages.grouper);
+        pipeline.set_analyzer(stages.analyzer);
+        *pipeline.postprocessors_mut() = stages.postprocessors;
+        *pipeline.outputs_mut() = stages.outputs;
+
+        Ok(())
+    }
+
+    fn instantiate(&self, definition: &PipelineDefinition) -> Result<Stages<I>> {
+        debug!(pipeline = %definition.name, "creating pipeline stages");
+
+        let preprocessors = definition
+            .preprocess
+            .iter()
+            .map(|stage| create(&self.preprocessors, "preprocessor", stage))
+            .collect::<Result<_>>()?;
+
+        let postprocessors = definition
+            .postprocess
+            .iter()
+            .map(|stage| create(&self.postprocessors, "post-processor", stage))
+            .collect::<Result<_>>()?;
+
+        let outputs = definition
+            .outputs
+            .iter()
+            .map(|stage| create(&self.postprocessors, "output", stage))
+            .collect::<Result<_>>()?;
+
+        Ok(Stages {
+            preprocessors,
+            extractor: create(&self.extractors, "extractor", &definition.extractor)?,
+            filter: definition.filter.clone(),
+            grouper: definition
+                .grouping
+                .clone()
+                .map(|strategy| Box::new(strategy) as Box<dyn ContourGrouper>),
+            analyzer: create(&self.analyzers, "analyzer", &definition.analyzer)?,
+            postprocessors,
+            outputs,
+        })
+    }
+}
+
+impl<I: ImageData> Default for StageRegistry<I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn create<T>(
+    factories: &HashMap<String, Factory<T>>,
+    category: &str,
+    stage: &StageDefinition,
+) -> Result<T> {
+    let factory = factories.get(&stage.kind).with_context(|| {
+        let mut names = factories.keys().map(String::as_str).collect::<Vec<_>>();
+        names.sort_unstable();
+
+        format!(
+            "no {category} named {:?} is registered; expected one of [{}]",
+            stage.kind,
+            names.join(", ")
+        )
+    })?;
+
+    factory(&stage.params).with_context(|| format!("creating {category} {:?}", stage.kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{
+        filter::FilterRule,
+        pipeline::tests::{ArrayImage, ColumnAnalyzer, PixelExtractor, TestCamera},
+        types::Image,
+    };
+
+    #[derive(Deserialize)]
+    struct FillConfig {
+        #[serde(default)]
+        columns: Vec<usize>,
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = StageRegistry::<ArrayImage>::new();
+        registry.register_extractor("pixels", |_: IgnoredAny| Ok(PixelExtractor));
+        registry.register_analyzer("column", |_: IgnoredAny| Ok(ColumnAnalyzer));
+        registry.register_preprocessor("fill", |config: FillConfig| {
+            Ok(move |image: &mut Image<ArrayImage>| {
+                let mut pixels = image.as_pixels_mut();
+                for &column in &config.columns {
+                    pixels[[0, column, 0]] = 255;
+                }
+                Ok(())
+            })
+        });
+
+        let definition = PipelineDefinition::from_json(
+            r#"{
+                "name": "test",
+                "camera": {
+                    "id": 0,
+                    "resolution": [4, 4],
+                    "pose": { "angle": 0, "dist": 1, "height": 0, "yaw": 0, "pitch": 0, "roll": 0 },
+                    "fov": [1, 1],
+                    "intrinsic_matrix": { "v": 1, "dim": [0, 0], "data": [] },
+                    "distortion_coeffs": { "v": 1, "dim": [0], "data": [] }
+                },
+                "preprocess": [{ "type": "fill", "params": { "columns": [1, 3] } }],
+                "extractor": { "type": "pixels" },
+                "analyzer": { "type": "column" },
+                "outputs": [{ "type": "robot_frame" }]
+            }"#,
+        )
+        .unwrap();
+
+        let camera = TestCamera {
+            config: definition.camera.clone(),
+            frames: 0,
+        };
+        let mut pipeline = registry.build(&definition, camera).unwrap();
+
+        let frame = pipeline.run_frame().unwrap();
+        assert_eq!(frame.targets.len(), 2);
+        assert_eq!(frame.targets[0].dist, 2.);
+        assert_eq!(frame.targets[1].dist, 4.);
+
+        // A definition with an unknown stage leaves the pipeline as it was.
+        let mut broken = definition.clone();
+        broken.filter = FilterChain::new(vec![FilterRule::Area(Default::default())]);
+        broken.analyzer.kind = "missing".to_owned();
+
+        let error = registry.configure(&mut pipeline, &broken).unwrap_err();
+        assert!(error.to_string().contains("[column, pinhole]"));
+        assert_eq!(pipeline.filter(), &definition.filter);
+        assert_eq!(pipeline.camera().config().resolution, (4, 4));
+    }
+}
@@ -0,0 +1,249 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{ensure, Context, Result};
+use tracing::info;
+
+use super::{Pipeline, PipelineDefinition, StageRegistry};
+use crate::traits::{Camera, ImageData};
+
+/// Watches a file for changes by polling its modification time.
+#[derive(Clone, Debug)]
+pub struct FileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl FileWatcher {
+    /// Watches a file, treating its current contents as already seen.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let modified = modified_time(&path);
+
+        Self { path, modified }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns whether the file has been modified since it was last polled.
+    ///
+    /// A file which is missing, as while an editor replaces it, is treated as
+    /// unchanged until it reappears.
+    pub fn poll(&mut self) -> bool {
+        match modified_time(&self.path) {
+            Some(modified) if Some(modified) != self.modified => {
+                self.modified = Some(modified);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Reloads a pipeline's definition, and the params files of its stages,
+/// whenever one of them changes.
+///
+/// A new definition is only applied if it parses and every one of its stages
+/// can be created. Otherwise, the pipeline keeps running the stages it had.
+/// The camera is never reopened, so a definition which changes the camera's
+/// config is rejected.
+pub struct PipelineReloader {
+    path: PathBuf,
+    definition: PipelineDefinition,
+    watchers: Vec<FileWatcher>,
+    interval: Duration,
+    last_poll: Instant,
+}
+
+impl PipelineReloader {
+    /// How often files are checked for changes by default.
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_millis(250);
+
+    /// Loads the definition at `path`, which is then watched for changes.
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let definition = PipelineDefinition::load(&path)?;
+        let watchers = watch(&path, &definition);
+
+        Ok(Self {
+            path,
+            definition,
+            watchers,
+            interval: Self::DEFAULT_INTERVAL,
+            last_poll: Instant::now(),
+        })
+    }
+
+    /// Sets how often files are checked for changes.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// The definition the pipeline is currently running.
+    pub fn definition(&self) -> &PipelineDefinition {
+        &self.definition
+    }
+
+    /// Checks whether any watched file has changed and, if so, applies the new
+    /// definition to `pipeline`.
+    ///
+    /// Returns whether a new definition was applied. If it was rejected, the
+    /// error is returned and the pipeline is left as it was; the files are
+    /// not read again until they next change.
+    pub fn reload<C, I>(
+        &mut self,
+        registry: &StageRegistry<I>,
+        pipeline: &mut Pipeline<C>,
+    ) -> Result<bool>
+    where
+        C: Camera<ImageStorage = I>,
+        I: ImageData,
+    {
+        if self.last_poll.elapsed() < self.interval {
+            return Ok(false);
+        }
+        self.last_poll = Instant::now();
+
+        // Poll every watcher, so that all of a batch of changes is seen now.
+        let mut changed = false;
+        for watcher in &mut self.watchers {
+            changed |= watcher.poll();
+        }
+
+        if !changed {
+            return Ok(false);
+        }
+
+        let definition = PipelineDefinition::load(&self.path)?;
+
+        let camera = serde_json::to_value(&definition.camera)?;
+        let current = serde_json::to_value(&self.definition.camera)?;
+        ensure!(
+            camera == current,
+            "camera config in {:?} changed; restart to apply it",
+            self.path
+        );
+
+        registry
+            .configure(pipeline, &definition)
+            .with_context(|| format!("applying pipeline definition {:?}", self.path))?;
+
+        // A stage may have started reading a different params file.
+        if !definition.params_files().eq(self.definition.params_files()) {
+            self.watchers = watch(&self.path, &definition);
+        }
+        self.definition = definition;
+
+        info!(path = ?self.path, "reloaded pipeline definition");
+
+        Ok(true)
+    }
+}
+
+fn watch(path: &Path, definition: &PipelineDefinition) -> Vec<FileWatcher> {
+    std::iter::once(path)
+        .chain(definition.params_files())
+        .map(FileWatcher::new)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use serde::de::IgnoredAny;
+
+    use super::*;
+    use crate::pipeline::tests::{ArrayImage, ColumnAnalyzer, PixelExtractor, TestCamera};
+
+    #[test]
+    fn test_reload() {
+        let dir = std::env::temp_dir().join(format!("stdvis-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let definition_path = dir.join("pipeline.json");
+        let params_path = dir.join("params.toml");
+
+        // Bumps a file's modification time, which may not change on a write
+        // within the file system's timestamp resolution.
+        let write = |path: &Path, contents: &str, age: u64| {
+            fs::write(path, contents).unwrap();
+            let time = SystemTime::now() - Duration::from_secs(age);
+            File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        };
+
+        let definition = |extractor: &str, params: &str| {
+            format!(
+                r#"{{
+                    "name": "test",
+                    "camera": {{
+                        "id": 0,
+                        "resolution": [4, 4],
+                        "pose": {{ "angle": 0, "dist": 0, "height": 0, "yaw": 0, "pitch": 0, "roll": 0 }},
+                        "fov": [1, 1],
+                        "intrinsic_matrix": {{ "v": 1, "dim": [0, 0], "data": [] }},
+                        "distortion_coeffs": {{ "v": 1, "dim": [0], "data": [] }}
+                    }},
+                    "extractor": {{ "type": "{extractor}" }},
+                    "analyzer": {{ "type": "column" }},
+                    "outputs": [{{ "type": "robot_frame", "params_file": "{params}" }}]
+                }}"#
+            )
+        };
+
+        write(&params_path, "", 20);
+        write(&dir.join("other.toml"), "", 20);
+        write(&definition_path, &definition("pixels", "params.toml"), 20);
+
+        let mut registry = StageRegistry::<ArrayImage>::new();
+        registry.register_extractor("pixels", |_: IgnoredAny| Ok(PixelExtractor));
+        registry.register_analyzer("column", |_: IgnoredAny| Ok(ColumnAnalyzer));
+
+        let mut reloader = PipelineReloader::new(&definition_path).unwrap();
+        reloader.set_interval(Duration::ZERO);
+
+        let camera = TestCamera {
+            config: reloader.definition().camera.clone(),
+            frames: 0,
+        };
+        let mut pipeline = registry.build(reloader.definition(), camera).unwrap();
+
+        assert!(!reloader.reload(&registry, &mut pipeline).unwrap());
+
+        // A broken definition is reported, and the old one is kept along with
+        // the params files it watches.
+        write(&definition_path, &definition("missing", "other.toml"), 10);
+        assert!(reloader.reload(&registry, &mut pipeline).is_err());
+        assert!(!reloader.reload(&registry, &mut pipeline).unwrap());
+        assert_eq!(reloader.definition().extractor.kind, "pixels");
+        assert_eq!(reloader.watchers[1].path(), params_path);
+
+        // Fixing the definition, or changing a params file, applies it.
+        write(&definition_path, &definition("pixels", "params.toml"), 5);
+        assert!(reloader.reload(&registry, &mut pipeline).unwrap());
+
+        write(&params_path, "unused = 1", 0);
+        assert!(reloader.reload(&registry, &mut pipeline).unwrap());
+        assert_eq!(reloader.definition().outputs[0].params["unused"], 1);
+
+        pipeline.run_frame().unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,367 @@
+//! Temporal filtering of a stream of measurements of one target.
+//!
+//! A `TargetSmoother` filters each of a target's `theta`, `dist`, `beta` and
+//! `height` separately, rejects measurements which jump too far from its
+//! estimate, and holds its last estimate with decaying confidence while the
+//! target is missing.
+
+use std::{collections::VecDeque, f64::consts::PI, time::Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{transform::normalize_angle, types::VisionTarget};
+
+/// The filter applied to each measured quantity.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub enum SmoothingMethod {
+    /// Pass measurements through unchanged.
+    #[default]
+    None,
+    /// The median of the latest `window` measurements.
+    Median { window: usize },
+    /// An exponential moving average, where `alpha` is the weight of each new
+    /// measurement.
+    Exponential { alpha: f64 },
+    /// The 1€ filter, which smooths heavily when the value is steady and
+    /// lightly when it moves quickly. Cutoffs are in hertz.
+    OneEuro {
+        min_cutoff: f64,
+        beta: f64,
+        derivative_cutoff: f64,
+    },
+}
+
+/// Parameters for a `TargetSmoother`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SmoothingConfig {
+    #[serde(default)]
+    pub method: SmoothingMethod,
+    /// The largest distance, in meters, between a measurement and the current
+    /// estimate for the measurement to be accepted.
+    #[serde(default)]
+    pub max_jump: Option<f64>,
+    /// The number of consecutive measurements which may be rejected as
+    /// outliers before the target is assumed to have really moved, and the
+    /// filter restarts from the latest measurement.
+    #[serde(default)]
+    pub max_rejections: u32,
+    /// The number of consecutive frames the last estimate is held for while
+    /// the target is missing.
+    #[serde(default)]
+    pub hold_frames: u32,
+    /// The factor confidence is multiplied by for each frame the target is
+    /// missing.
+    #[serde(default = "SmoothingConfig::default_confidence_decay")]
+    pub confidence_decay: f32,
+}
+
+impl SmoothingConfig {
+    fn default_confidence_decay() -> f32 {
+        0.5
+    }
+}
+
+impl Default for SmoothingConfig {
+    fn default() -> Self {
+        Self {
+            method: SmoothingMethod::default(),
+            max_jump: None,
+            max_rejections: 0,
+            hold_frames: 0,
+            confidence_decay: Self::default_confidence_decay(),
+        }
+    }
+}
+
+/// The filter state of a single quantity.
+#[derive(Clone, Debug, Default)]
+struct Channel {
+    history: VecDeque<f64>,
+    value: Option<f64>,
+    derivative: f64,
+}
+
+impl Channel {
+    fn filter(&mut self, method: &SmoothingMethod, sample: f64, dt: f64) -> f64 {
+        let previous = match self.value {
+            Some(previous) => previous,
+            None => {
+                self.history.push_back(sample);
+                self.value = Some(sample);
+                return sample;
+            }
+        };
+
+        let value = match *method {
+            SmoothingMethod::None => sample,
+            SmoothingMethod::Median { window } => {
+                self.history.push_back(sample);
+                while self.history.len() > window.max(1) {
+                    self.history.pop_front();
+                }
+
+                let mut sorted = self.history.iter().copied().collect::<Vec<_>>();
+                sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+                let mid = sorted.len() / 2;
+                if sorted.len() % 2 == 0 {
+                    (sorted[mid - 1] + sorted[mid]) / 2.
+                } else {
+                    sorted[mid]
+                }
+            }
+            SmoothingMethod::Exponential { alpha } => {
+                previous + alpha.clamp(0., 1.) * (sample - previous)
+            }
+            SmoothingMethod::OneEuro {
+                min_cutoff,
+                beta,
+                derivative_cutoff,
+            } => {
+                if dt <= 0. {
+                    return previous;
+                }
+
+                let derivative = (sample - previous) / dt;
+                self.derivative +=
+                    smoothing_factor(derivative_cutoff, dt) * (derivative - self.derivative);
+
+                let cutoff = min_cutoff + beta * self.derivative.abs();
+                previous + smoothing_factor(cutoff, dt) * (sample - previous)
+            }
+        };
+
+        self.value = Some(value);
+        value
+    }
+
+    /// Shifts the channel's state so that an angle sample can be filtered
+    /// without jumping across the wraparound.
+    fn unwrap(&self, angle: f64) -> f64 {
+        match self.value {
+            Some(previous) => previous + normalize_angle(angle - previous),
+            None => angle,
+        }
+    }
+}
+
+/// Returns the weight of a new sample in a low-pass filter with the given
+/// cutoff frequency.
+fn smoothing_factor(cutoff: f64, dt: f64) -> f64 {
+    let tau = 1. / (2. * PI * cutoff);
+    1. / (1. + tau / dt)
+}
+
+/// Smooths a stream of measurements of one target.
+pub struct TargetSmoother {
+    config: SmoothingConfig,
+    /// The channels for `theta`, `dist`, `beta` and `height`, in that order.
+    channels: [Channel; 4],
+    estimate: Option<VisionTarget>,
+    last_time: Option<Instant>,
+    rejections: u32,
+    misses: u32,
+}
+
+impl TargetSmoother {
+    pub fn new(config: SmoothingConfig) -> Self {
+        Self {
+            config,
+            channels: Default::default(),
+            estimate: None,
+            last_time: None,
+            rejections: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn config(&self) -> &SmoothingConfig {
+        &self.config
+    }
+
+    /// Forgets every previous measurement.
+    pub fn reset(&mut self) {
+        self.channels = Default::default();
+        self.estimate = None;
+        self.last_time = None;
+        self.rejections = 0;
+        self.misses = 0;
+    }
+
+    /// Adds the measurement from a frame captured at `time`, or `None` if the
+    /// target wasn't seen, and returns the current estimate.
+    ///
+    /// A measurement rejected as an outlier leaves the current estimate
+    /// unchanged. While the target is missing, the last estimate is returned
+    /// for up to `hold_frames` frames, with its confidence decaying each
+    /// frame.
+    pub fn update(&mut self, time: Instant, target: Option<&VisionTarget>) -> Option<VisionTarget> {
+        let target = match target {
+            Some(target) if self.is_outlier(target) => return self.estimate.clone(),
+            Some(target) => target,
+            None => return self.hold(),
+        };
+
+        let dt = match self.last_time {
+            Some(last) => time.saturating_duration_since(last).as_secs_f64(),
+            None => 0.,
+        };
+        self.last_time = Some(time);
+        self.rejections = 0;
+        self.misses = 0;
+
+        let method = &self.config.method;
+        let [theta, dist, beta, height] = &mut self.channels;
+
+        let sample = theta.unwrap(target.theta);
+        let theta = normalize_angle(theta.filter(method, sample, dt));
+        let sample = beta.unwrap(target.beta);
+        let beta = normalize_angle(beta.filter(method, sample, dt));
+
+        let estimate = VisionTarget {
+            theta,
+            dist: dist.filter(method, target.dist, dt),
+            beta,
+            height: height.filter(method, target.height, dt),
+            ..target.clone()
+        };
+
+        self.estimate = Some(estimate.clone());
+        Some(estimate)
+    }
+
+    /// Checks a measurement against the current estimate, counting it if it
+    /// is rejected.
+    fn is_outlier(&mut self, target: &VisionTarget) -> bool {
+        let (max_jump, estimate) = match (self.config.max_jump, &self.estimate) {
+            (Some(max_jump), Some(estimate)) => (max_jump, estimate),
+            _ => return false,
+        };
+
+        let (a, b) = (target.position(), estimate.position());
+        let jump = (0..3)
+            .map(|idx| (a[idx] - b[idx]).powi(2))
+            .sum::<f64>()
+            .sqrt();
+
+        if jump <= max_jump {
+            return false;
+        }
+
+        if self.rejections >= self.config.max_rejections {
+            // The target has consistently been somewhere else, so start over.
+            self.reset();
+            return false;
+        }
+
+        self.rejections += 1;
+        true
+    }
+
+    fn hold(&mut self) -> Option<VisionTarget> {
+        self.misses += 1;
+
+        if self.misses > self.config.hold_frames {
+            self.reset();
+            return None;
+        }
+
+        let estimate = self.estimate.as_ref()?;
+        let decay = self.config.confidence_decay.powi(self.misses as i32);
+
+        Some(VisionTarget {
+            confidence: estimate.confidence * decay,
+            ..estimate.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn target(dist: f64) -> VisionTarget {
+        VisionTarget {
+            dist,
+            confidence: 1.,
+            ..VisionTarget::default()
+        }
+    }
+
+    #[test]
+    fn test_smoothing_and_hold() {
+        let mut smoother = TargetSmoother::new(SmoothingConfig {
+            method: SmoothingMethod::Exponential { alpha: 0.5 },
+            max_jump: Some(1.),
+            max_rejections: 1,
+            hold_frames: 2,
+            confidence_decay: 0.5,
+        });
+        let start = Instant::now();
+        let mut frame = (0..).map(|idx| start + Duration::from_millis(20) * idx);
+
+        let mut update = |target: Option<VisionTarget>| {
+            smoother
+                .update(frame.next().unwrap(), target.as_ref())
+                .map(|target| (target.dist, target.confidence))
+        };
+
+        assert_eq!(update(Some(target(2.))), Some((2., 1.)));
+        assert_eq!(update(Some(target(3.))), Some((2.5, 1.)));
+
+        // A single jump is rejected, but a second one is accepted.
+        assert_eq!(update(Some(target(6.))), Some((2.5, 1.)));
+        assert_eq!(update(Some(target(6.))), Some((6., 1.)));
+
+        assert_eq!(update(None), Some((6., 0.5)));
+        assert_eq!(update(None), Some((6., 0.25)));
+        assert_eq!(update(None), None);
+        assert_eq!(update(Some(target(1.))), Some((1., 1.)));
+    }
+
+    #[test]
+    fn test_rejection_without_hold() {
+        let mut smoother = TargetSmoother::new(SmoothingConfig {
+            method: SmoothingMethod::Exponential { alpha: 0.5 },
+            max_jump: Some(1.),
+            max_rejections: 1,
+            hold_frames: 0,
+            ..SmoothingConfig::default()
+        });
+        let now = Instant::now();
+        let mut update = |dist| smoother.update(now, Some(&target(dist))).map(|t| t.dist);
+
+        assert_eq!(update(2.), Some(2.));
+        assert_eq!(update(3.), Some(2.5));
+
+        // One outlier keeps the estimate, and a second restarts the filter
+        // from the new measurement.
+        assert_eq!(update(6.), Some(2.5));
+        assert_eq!(update(6.), Some(6.));
+        assert_eq!(update(6.5), Some(6.25));
+    }
+
+    #[test]
+    fn test_angle_wraparound() {
+        let mut smoother = TargetSmoother::new(SmoothingConfig {
+            method: SmoothingMethod::Median { window: 3 },
+            ..SmoothingConfig::default()
+        });
+        let now = Instant::now();
+        let mut theta = 0.;
+
+        // The median is taken across the wraparound, rather than of values on
+        // either side of it.
+        for sample in [PI - 0.1, -PI + 0.1, PI - 0.05] {
+            let target = VisionTarget {
+                theta: sample,
+                ..VisionTarget::default()
+            };
+            theta = smoother.update(now, Some(&target)).unwrap().theta;
+        }
+
+        assert!((theta - (PI - 0.05)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,76 @@
+//! Timestamps which can be sent to, and compared on, another machine.
+//!
+//! `Instant`s are only meaningful within the process that made them, so
+//! targets carry wall-clock times in microseconds since the Unix epoch. The
+//! robot can rewind its odometry to a target's capture time, provided the two
+//! clocks are synchronized.
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    traits::ImageData,
+    types::{Image, VisionTarget},
+};
+
+/// Converts an instant into microseconds since the Unix epoch, using the
+/// current offset between the monotonic and wall clocks.
+pub fn unix_micros(instant: Instant) -> u64 {
+    let now = Instant::now();
+    let wall = SystemTime::now();
+
+    let time = if instant <= now {
+        wall.checked_sub(now - instant)
+    } else {
+        wall.checked_add(instant - now)
+    };
+
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_micros() as u64)
+}
+
+/// Converts microseconds since the Unix epoch into a wall-clock time.
+pub fn from_unix_micros(micros: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_micros(micros)
+}
+
+impl VisionTarget {
+    /// Records the frame a target was seen in and when it finished being
+    /// measured.
+    pub fn stamp<I: ImageData>(&mut self, image: &Image<I>, processed: Instant) {
+        let latency = processed.saturating_duration_since(image.timestamp);
+
+        self.frame = image.sequence;
+        self.capture_time = unix_micros(image.timestamp);
+        self.latency = latency.as_micros().min(u32::MAX as u128) as u32;
+    }
+
+    /// When the target finished being measured, in microseconds since the
+    /// Unix epoch.
+    pub fn processed_time(&self) -> u64 {
+        self.capture_time + self.latency as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unix_micros() {
+        let now = Instant::now();
+        let wall = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_micros() as u64;
+
+        let earlier = unix_micros(now - Duration::from_millis(50));
+        let later = unix_micros(now + Duration::from_millis(50));
+
+        assert!(wall.abs_diff(earlier + 50_000) < 5_000);
+        assert!(wall.abs_diff(later - 50_000) < 5_000);
+        assert_eq!(
+            from_unix_micros(later),
+            UNIX_EPOCH + Duration::from_micros(later)
+        );
+    }
+}
@@ -0,0 +1,667 @@
+//! Association of targets across frames into tracks with stable ids.
+//!
+//! Each track follows one target's floor position with a constant-velocity
+//! Kalman filter. Every frame, detections are matched to the tracks' predicted
+//! positions by minimum total Mahalanobis distance, using the Hungarian
+//! algorithm. Unmatched detections start new tentative tracks, which are
+//! confirmed after being matched for several frames. Confirmed tracks coast on
+//! their predictions when they go unmatched, and are dropped after coasting
+//! for too long.
+
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{transform::normalize_angle, types::VisionTarget, uncertainty::Uncertainty};
+
+/// The cost given to pairs of tracks and detections which may not be matched.
+const FORBIDDEN: f64 = 1e12;
+
+type Matrix2 = [[f64; 2]; 2];
+type Matrix4 = [[f64; 4]; 4];
+
+/// Parameters for a `Tracker`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrackerConfig {
+    /// The standard deviation of targets' acceleration, in meters per second
+    /// squared.
+    #[serde(default = "TrackerConfig::default_process_noise")]
+    pub process_noise: f64,
+    /// The standard deviation of measured positions, in meters, for targets
+    /// given without an `Uncertainty`.
+    #[serde(default = "TrackerConfig::default_measurement_noise")]
+    pub measurement_noise: f64,
+    /// The standard deviation of a new track's velocity, in meters per
+    /// second.
+    #[serde(default = "TrackerConfig::default_initial_velocity_noise")]
+    pub initial_velocity_noise: f64,
+    /// The largest squared Mahalanobis distance between a track's predicted
+    /// position and a detection for them to be matched.
+    #[serde(default = "TrackerConfig::default_gate")]
+    pub gate: f64,
+    /// The number of frames a new track must be matched in to be confirmed.
+    #[serde(default = "TrackerConfig::default_confirm_hits")]
+    pub confirm_hits: u32,
+    /// The number of consecutive frames a confirmed track may go unmatched
+    /// before it is dropped.
+    #[serde(default = "TrackerConfig::default_max_coast")]
+    pub max_coast: u32,
+    /// Whether detections may only be matched to tracks of the same target
+    /// id, for targets whose ids identify them.
+    #[serde(default)]
+    pub match_ids: bool,
+}
+
+impl TrackerConfig {
+    fn default_process_noise() -> f64 {
+        2.
+    }
+
+    fn default_measurement_noise() -> f64 {
+        0.1
+    }
+
+    fn default_initial_velocity_noise() -> f64 {
+        1.
+    }
+
+    fn default_gate() -> f64 {
+        // The 99th percentile of the chi-squared distribution with two
+        // degrees of freedom.
+        9.21
+    }
+
+    fn default_confirm_hits() -> u32 {
+        3
+    }
+
+    fn default_max_coast() -> u32 {
+        5
+    }
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        Self {
+            process_noise: Self::default_process_noise(),
+            measurement_noise: Self::default_measurement_noise(),
+            initial_velocity_noise: Self::default_initial_velocity_noise(),
+            gate: Self::default_gate(),
+            confirm_hits: Self::default_confirm_hits(),
+            max_coast: Self::default_max_coast(),
+            match_ids: false,
+        }
+    }
+}
+
+/// The stage of a track's life.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrackStatus {
+    /// The track has not been matched in enough frames to be trusted.
+    Tentative,
+    /// The track was matched in the latest frame.
+    Confirmed,
+    /// The track was not matched in the latest frame, and its position is a
+    /// prediction.
+    Coasting,
+}
+
+/// A target followed across frames.
+#[derive(Clone, Debug)]
+pub struct Track {
+    id: u32,
+    status: TrackStatus,
+    /// The position and velocity `[x, y, vx, vy]` on the floor plane.
+    state: [f64; 4],
+    covariance: Matrix4,
+    hits: u32,
+    misses: u32,
+    last_seen: Instant,
+    /// The latest detection matched to the track.
+    detection: VisionTarget,
+}
+
+impl Track {
+    /// The track's id, which is never 0 and is never reused by the tracker
+    /// that assigned it.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn status(&self) -> TrackStatus {
+        self.status
+    }
+
+    /// The filtered position `(x, y)` on the floor plane.
+    pub fn position(&self) -> [f64; 2] {
+        [self.state[0], self.state[1]]
+    }
+
+    /// The filtered velocity on the floor plane, per second.
+    pub fn velocity(&self) -> [f64; 2] {
+        [self.state[2], self.state[3]]
+    }
+
+    /// The covariance of the filtered position.
+    pub fn position_covariance(&self) -> Matrix2 {
+        let p = &self.covariance;
+        [[p[0][0], p[0][1]], [p[1][0], p[1][1]]]
+    }
+
+    /// The number of consecutive frames the track has gone unmatched.
+    pub fn misses(&self) -> u32 {
+        self.misses
+    }
+
+    /// The time of the latest frame the track was matched in.
+    pub fn last_seen(&self) -> Instant {
+        self.last_seen
+    }
+
+    /// The latest detection matched to the track, as it was measured.
+    pub fn detection(&self) -> &VisionTarget {
+        &self.detection
+    }
+
+    /// Returns the track's filtered target, carrying the track's id. The
+    /// height, facing and confidence are those of the latest detection.
+    pub fn target(&self) -> VisionTarget {
+        let [x, y] = self.position();
+        let theta = y.atan2(x);
+        let facing = self.detection.theta + self.detection.beta;
+
+        VisionTarget {
+            theta,
+            dist: x.hypot(y),
+            beta: normalize_angle(facing - theta),
+            track: self.id,
+            ..self.detection.clone()
+        }
+    }
+
+    fn predict(&mut self, dt: f64, process_noise: f64) {
+        let [x, y, vx, vy] = self.state;
+        self.state = [x + vx * dt, y + vy * dt, vx, vy];
+
+        let mut f = identity();
+        f[0][2] = dt;
+        f[1][3] = dt;
+
+        let mut covariance = multiply(&multiply(&f, &self.covariance), &transpose(&f));
+
+        let variance = process_noise * process_noise;
+        for axis in 0..2 {
+            let (pos, vel) = (axis, axis + 2);
+            covariance[pos][pos] += dt.powi(4) / 4. * variance;
+            covariance[pos][vel] += dt.powi(3) / 2. * variance;
+            covariance[vel][pos] += dt.powi(3) / 2. * variance;
+            covariance[vel][vel] += dt * dt * variance;
+        }
+
+        self.covariance = covariance;
+    }
+
+    /// Returns the innovation of a measurement and its covariance.
+    fn innovation(&self, position: [f64; 2], noise: &Matrix2) -> ([f64; 2], Matrix2) {
+        let p = &self.covariance;
+        let residual = [position[0] - self.state[0], position[1] - self.state[1]];
+        let covariance = [
+            [p[0][0] + noise[0][0], p[0][1] + noise[0][1]],
+            [p[1][0] + noise[1][0], p[1][1] + noise[1][1]],
+        ];
+
+        (residual, covariance)
+    }
+
+    /// Returns the squared Mahalanobis distance of a measurement from the
+    /// track's predicted position.
+    fn distance(&self, position: [f64; 2], noise: &Matrix2) -> f64 {
+        let (residual, covariance) = self.innovation(position, noise);
+
+        match invert(&covariance) {
+            Some(inverse) => quadratic(&inverse, residual),
+            None => FORBIDDEN,
+        }
+    }
+
+    fn correct(&mut self, position: [f64; 2], noise: &Matrix2) {
+        let (residual, covariance) = self.innovation(position, noise);
+        let inverse = match invert(&covariance) {
+            Some(inverse) => inverse,
+            None => return,
+        };
+
+        let p = self.covariance;
+        let mut gain = [[0.; 2]; 4];
+        for (row, gain) in gain.iter_mut().enumerate() {
+            for (col, gain) in gain.iter_mut().enumerate() {
+                *gain = (0..2).map(|k| p[row][k] * inverse[k][col]).sum();
+            }
+        }
+
+        let rows = self.state.iter_mut().zip(self.covariance.iter_mut());
+        for (gain, (state, covariance)) in gain.iter().zip(rows) {
+            *state += gain[0] * residual[0] + gain[1] * residual[1];
+
+            for (col, value) in covariance.iter_mut().enumerate() {
+                *value -= gain[0] * p[0][col] + gain[1] * p[1][col];
+            }
+        }
+    }
+}
+
+/// Follows targets across frames, assigning each a stable track id.
+pub struct Tracker {
+    config: TrackerConfig,
+    tracks: Vec<Track>,
+    next_id: u32,
+    last_update: Option<Instant>,
+}
+
+impl Tracker {
+    pub fn new(config: TrackerConfig) -> Self {
+        Self {
+            config,
+            tracks: Vec::new(),
+            next_id: 1,
+            last_update: None,
+        }
+    }
+
+    pub fn config(&self) -> &TrackerConfig {
+        &self.config
+    }
+
+    /// Every live track, including tentative ones.
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    /// The tracks which have been confirmed, whether or not they were matched
+    /// in the latest frame.
+    pub fn confirmed(&self) -> impl Iterator<Item = &Track> {
+        self.tracks
+            .iter()
+            .filter(|track| track.status != TrackStatus::Tentative)
+    }
+
+    /// Removes every track.
+    pub fn clear(&mut self) {
+        self.tracks.clear();
+        self.last_update = None;
+    }
+
+    /// Advances the tracks to `time` and matches them to the targets seen in a
+    /// frame captured then, using the configured measurement noise.
+    ///
+    /// Every target must be measured in the same frame of reference.
+    pub fn update(&mut self, time: Instant, targets: &[VisionTarget]) -> &[Track] {
+        let noise = self.config.measurement_noise.powi(2);
+        let detections = targets
+            .iter()
+            .map(|target| (target.clone(), [[noise, 0.], [0., noise]]))
+            .collect();
+
+        self.step(time, detections)
+    }
+
+    /// Like [`update`](Self::update), but weights each target by its own
+    /// measurement uncertainty.
+    pub fn update_measured(
+        &mut self,
+        time: Instant,
+        targets: &[(VisionTarget, Uncertainty)],
+    ) -> &[Track] {
+        let detections = targets
+            .iter()
+            .map(|(target, uncertainty)| (target.clone(), uncertainty.position_covariance(target)))
+            .collect();
+
+        self.step(time, detections)
+    }
+
+    fn step(&mut self, time: Instant, detections: Vec<(VisionTarget, Matrix2)>) -> &[Track] {
+        let dt = match self.last_update {
+            Some(last) => time.saturating_duration_since(last).as_secs_f64(),
+            None => 0.,
+        };
+        self.last_update = Some(time);
+
+        for track in &mut self.tracks {
+            track.predict(dt, self.config.process_noise);
+        }
+
+        let positions = detections
+            .iter()
+            .map(|(target, _)| {
+                let [x, y, _] = target.position();
+                [x, y]
+            })
+            .collect::<Vec<_>>();
+
+        let costs = self
+            .tracks
+            .iter()
+            .map(|track| {
+                detections
+                    .iter()
+                    .zip(&positions)
+                    .map(|((target, noise), &position)| {
+                        if self.config.match_ids && target.id != track.detection.id {
+                            return FORBIDDEN;
+                        }
+
+                        let distance = track.distance(position, noise);
+                        if distance <= self.config.gate {
+                            distance
+                        } else {
+                            FORBIDDEN
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let assignment = assign(&costs, detections.len());
+        let mut matched = vec![false; detections.len()];
+
+        for (track, (costs, assigned)) in self.tracks.iter_mut().zip(costs.iter().zip(assignment)) {
+            match assigned.filter(|&idx| costs[idx] < FORBIDDEN) {
+                Some(idx) => {
+                    let (target, noise) = &detections[idx];
+                    track.correct(positions[idx], noise);
+                    track.detection = target.clone();
+                    track.last_seen = time;
+                    track.hits += 1;
+                    track.misses = 0;
+
+                    if track.status != TrackStatus::Tentative
+                        || track.hits >= self.config.confirm_hits
+                    {
+                        track.status = TrackStatus::Confirmed;
+                    }
+
+                    matched[idx] = true;
+                }
+                None => {
+                    track.misses += 1;
+
+                    if track.status == TrackStatus::Confirmed {
+                        track.status = TrackStatus::Coasting;
+                    }
+                }
+            }
+        }
+
+        let max_coast = self.config.max_coast;
+        self.tracks.retain(|track| match track.status {
+            TrackStatus::Tentative => track.misses == 0,
+            _ => track.misses <= max_coast,
+        });
+
+        for ((target, noise), (position, matched)) in detections
+            .into_iter()
+            .zip(positions.into_iter().zip(matched))
+        {
+            if matched {
+                continue;
+            }
+
+            let velocity = self.config.initial_velocity_noise.powi(2);
+            let mut covariance = [[0.; 4]; 4];
+            for (row, values) in noise.iter().enumerate() {
+                covariance[row][..2].copy_from_slice(values);
+            }
+            covariance[2][2] = velocity;
+            covariance[3][3] = velocity;
+
+            let status = if self.config.confirm_hits <= 1 {
+                TrackStatus::Confirmed
+            } else {
+                TrackStatus::Tentative
+            };
+
+            self.tracks.push(Track {
+                id: self.next_id,
+                status,
+                state: [position[0], position[1], 0., 0.],
+                covariance,
+                hits: 1,
+                misses: 0,
+                last_seen: time,
+                detection: target,
+            });
+            self.next_id = self.next_id.wrapping_add(1).max(1);
+        }
+
+        &self.tracks
+    }
+}
+
+/// Solves the assignment problem with the Hungarian algorithm, returning the
+/// column matched to each row such that the total cost is minimal.
+///
+/// Every row is matched if there are at least as many columns as rows, and
+/// every column is matched otherwise.
+fn assign(costs: &[Vec<f64>], cols: usize) -> Vec<Option<usize>> {
+    let rows = costs.len();
+
+    if rows == 0 || cols == 0 {
+        return vec![None; rows];
+    }
+
+    if rows > cols {
+        let transposed = (0..cols)
+            .map(|col| costs.iter().map(|row| row[col]).collect())
+            .collect::<Vec<_>>();
+
+        let mut assignment = vec![None; rows];
+        for (col, row) in assign(&transposed, rows).into_iter().enumerate() {
+            if let Some(row) = row {
+                assignment[row] = Some(col);
+            }
+        }
+
+        return assignment;
+    }
+
+    // Potentials and matches are indexed from 1, with 0 as a sentinel.
+    let mut u = vec![0.; rows + 1];
+    let mut v = vec![0.; cols + 1];
+    let mut matches = vec![0; cols + 1];
+    let mut way = vec![0; cols + 1];
+
+    for row in 1..=rows {
+        matches[0] = row;
+        let mut col0 = 0;
+        let mut min_slack = vec![f64::INFINITY; cols + 1];
+        let mut used = vec![false; cols + 1];
+
+        loop {
+            used[col0] = true;
+            let row0 = matches[col0];
+            let mut delta = f64::INFINITY;
+            let mut col1 = 0;
+
+            for col in 1..=cols {
+                if used[col] {
+                    continue;
+                }
+
+                let slack = costs[row0 - 1][col - 1] - u[row0] - v[col];
+                if slack < min_slack[col] {
+                    min_slack[col] = slack;
+                    way[col] = col0;
+                }
+                if min_slack[col] < delta {
+                    delta = min_slack[col];
+                    col1 = col;
+                }
+            }
+
+            for col in 0..=cols {
+                if used[col] {
+                    u[matches[col]] += delta;
+                    v[col] -= delta;
+                } else {
+                    min_slack[col] -= delta;
+                }
+            }
+
+            col0 = col1;
+            if matches[col0] == 0 {
+                break;
+            }
+        }
+
+        loop {
+            let col1 = way[col0];
+            matches[col0] = matches[col1];
+            col0 = col1;
+
+            if col0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut assignment = vec![None; rows];
+    for (col, &row) in matches.iter().enumerate().skip(1) {
+        if row != 0 {
+            assignment[row - 1] = Some(col - 1);
+        }
+    }
+
+    assignment
+}
+
+fn identity() -> Matrix4 {
+    let mut out = [[0.; 4]; 4];
+    for (idx, row) in out.iter_mut().enumerate() {
+        row[idx] = 1.;
+    }
+
+    out
+}
+
+fn multiply(a: &Matrix4, b: &Matrix4) -> Matrix4 {
+    let mut out = [[0.; 4]; 4];
+    for (row, out) in out.iter_mut().enumerate() {
+        for (col, out) in out.iter_mut().enumerate() {
+            *out = (0..4).map(|k| a[row][k] * b[k][col]).sum();
+        }
+    }
+
+    out
+}
+
+fn transpose(m: &Matrix4) -> Matrix4 {
+    let mut out = [[0.; 4]; 4];
+    for (row, values) in m.iter().enumerate() {
+        for (col, value) in values.iter().enumerate() {
+            out[col][row] = *value;
+        }
+    }
+
+    out
+}
+
+fn invert(m: &Matrix2) -> Option<Matrix2> {
+    let det = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+
+    if det.abs() < f64::EPSILON {
+        return None;
+    }
+
+    Some([
+        [m[1][1] / det, -m[0][1] / det],
+        [-m[1][0] / det, m[0][0] / det],
+    ])
+}
+
+/// Returns `v^T * m * v`.
+fn quadratic(m: &Matrix2, v: [f64; 2]) -> f64 {
+    (0..2)
+        .map(|row| (0..2).map(|col| v[row] * m[row][col] * v[col]).sum::<f64>())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn target_at(x: f64, y: f64) -> VisionTarget {
+        VisionTarget {
+            theta: y.atan2(x),
+            dist: x.hypot(y),
+            confidence: 1.,
+            ..VisionTarget::default()
+        }
+    }
+
+    #[test]
+    fn test_assign() {
+        let costs = vec![vec![4., 1., 3.], vec![2., 0., 5.], vec![3., 2., 2.]];
+        assert_eq!(assign(&costs, 3), vec![Some(1), Some(0), Some(2)]);
+
+        let costs = vec![vec![1.], vec![0.]];
+        assert_eq!(assign(&costs, 1), vec![None, Some(0)]);
+    }
+
+    #[test]
+    fn test_track_lifecycle() {
+        let mut tracker = Tracker::new(TrackerConfig::default());
+        let start = Instant::now();
+        let frame = |idx: u32| start + Duration::from_millis(20) * idx;
+
+        // Two targets moving apart, listed in a different order every frame.
+        // Each keeps its track's id, whatever its per-frame id.
+        for idx in 0..3 {
+            let offset = 1. + 0.02 * idx as f64;
+            let mut targets = vec![target_at(3., offset), target_at(3., -offset)];
+            if idx % 2 == 1 {
+                targets.reverse();
+            }
+            for (id, target) in targets.iter_mut().enumerate() {
+                target.id = id as u16;
+            }
+
+            for track in tracker.update(frame(idx), &targets) {
+                let target = track.target();
+                let expected = if target.position()[1] > 0. { 1 } else { 2 };
+                assert_eq!(target.track, expected);
+            }
+        }
+
+        let mut tracks = tracker.confirmed().collect::<Vec<_>>();
+        tracks.sort_by(|a, b| a.position()[1].partial_cmp(&b.position()[1]).unwrap());
+        assert_eq!(tracks.len(), 2);
+        assert_eq!((tracks[0].id(), tracks[1].id()), (2, 1));
+        assert!((1.0..1.05).contains(&tracks[1].position()[1]));
+        assert!(tracks[1].velocity()[1] > 0.);
+
+        // One target drops out, and coasts until it is dropped.
+        for idx in 3..9 {
+            let offset = 1. + 0.02 * idx as f64;
+            tracker.update(frame(idx), &[target_at(3., offset)]);
+
+            let statuses = tracker
+                .tracks()
+                .iter()
+                .map(|track| (track.id(), track.status()))
+                .collect::<Vec<_>>();
+
+            if idx < 8 {
+                assert_eq!(
+                    statuses,
+                    vec![(1, TrackStatus::Confirmed), (2, TrackStatus::Coasting)]
+                );
+            } else {
+                assert_eq!(statuses, vec![(1, TrackStatus::Confirmed)]);
+                assert_eq!(tracker.tracks()[0].target().track, 1);
+            }
+        }
+    }
+}
@@ -3,7 +3,7 @@ use std::io;
 use anyhow::Result;
 use ndarray::{ArrayViewD, ArrayViewMutD};
 
-use crate::types::*;
+use crate::{transform::Transform, types::*};
 
 /// A camera which captures images backed by a given `DataSource`.
 pub trait Camera {
@@ -42,7 +42,42 @@ pub trait ContourExtractor {
     ) -> Result<Vec<ContourGroup<'src>>>;
 }
 
+/// An interface that arranges contours into logical groups.
+pub trait ContourGrouper {
+    /// Groups contours which were all found in an image from `camera`.
+    fn group<'src>(
+        &self,
+        camera: &'src CameraConfig,
+        contours: Vec<Contour>,
+    ) -> Vec<ContourGroup<'src>>;
+
+    /// Discards the existing grouping of `groups` and groups their contours
+    /// again. Every group is expected to come from the same camera.
+    fn regroup<'src>(&self, groups: Vec<ContourGroup<'src>>) -> Vec<ContourGroup<'src>> {
+        let camera = match groups.first() {
+            Some(group) => group.camera,
+            None => return Vec::new(),
+        };
+
+        let contours = groups
+            .into_iter()
+            .flat_map(|group| group.contours)
+            .collect();
+
+        self.group(camera, contours)
+    }
+}
+
 /// An interface that computes a `VisionTarget` given a `ContourGroup`.
 pub trait ContourAnalyzer {
     fn analyze(&self, contours: &ContourGroup) -> Result<VisionTarget>;
+
+    /// Like `analyze`, but also returns the pose of the target's model in the
+    /// camera frame, for analyzers which solve for one.
+    fn analyze_with_pose(
+        &self,
+        contours: &ContourGroup,
+    ) -> Result<(VisionTarget, Option<Transform>)> {
+        Ok((self.analyze(contours)?, None))
+    }
 }
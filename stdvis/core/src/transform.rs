@@ -0,0 +1,280 @@
+//! Conversions between camera-relative and robot-relative frames of reference.
+//!
+//! The robot frame has its origin at the center of the robot on the floor, with
+//! `x` pointing forward, `y` pointing left and `z` pointing up. A camera frame
+//! uses the same axis directions as seen from the camera, with its origin at
+//! the camera's optical center. A camera's [`Pose`] in its `CameraConfig`
+//! describes where that frame sits within the robot frame.
+
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{CameraConfig, Pose, VisionTarget};
+
+/// A point or direction in 3D space.
+pub type Vector3 = [f64; 3];
+
+/// A rigid transformation, applied as a rotation followed by a translation.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transform {
+    pub rotation: [[f64; 3]; 3],
+    pub translation: Vector3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        rotation: [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]],
+        translation: [0., 0., 0.],
+    };
+
+    /// Creates a transform from yaw, pitch and roll angles (using the same
+    /// conventions as [`Pose`]) and a translation.
+    pub fn from_euler(yaw: f64, pitch: f64, roll: f64, translation: Vector3) -> Self {
+        let (sy, cy) = yaw.sin_cos();
+        // Positive pitch tilts the forward axis up, which is a negative
+        // rotation about the left-pointing `y` axis.
+        let (sp, cp) = (-pitch).sin_cos();
+        let (sr, cr) = roll.sin_cos();
+
+        Self {
+            rotation: [
+                [cy * cp, cy * sp * sr - sy * cr, cy * sp * cr + sy * sr],
+                [sy * cp, sy * sp * sr + cy * cr, sy * sp * cr - cy * sr],
+                [-sp, cp * sr, cp * cr],
+            ],
+            translation,
+        }
+    }
+
+    /// Returns the yaw, pitch and roll angles of this transform's rotation.
+    pub fn euler(&self) -> (f64, f64, f64) {
+        let r = &self.rotation;
+
+        let yaw = r[1][0].atan2(r[0][0]);
+        let pitch = r[2][0].clamp(-1., 1.).asin();
+        let roll = r[2][1].atan2(r[2][2]);
+
+        (yaw, pitch, roll)
+    }
+
+    /// Rotates a direction without translating it.
+    pub fn rotate(&self, vector: Vector3) -> Vector3 {
+        let r = &self.rotation;
+        let mut out = [0.; 3];
+
+        for (row, out) in r.iter().zip(out.iter_mut()) {
+            *out = row[0] * vector[0] + row[1] * vector[1] + row[2] * vector[2];
+        }
+
+        out
+    }
+
+    /// Maps a point from this transform's local frame into its parent frame.
+    pub fn apply(&self, point: Vector3) -> Vector3 {
+        let rotated = self.rotate(point);
+
+        [
+            rotated[0] + self.translation[0],
+            rotated[1] + self.translation[1],
+            rotated[2] + self.translation[2],
+        ]
+    }
+
+    /// Returns the transform that maps points from the parent frame back into
+    /// the local frame.
+    pub fn inverse(&self) -> Self {
+        let r = &self.rotation;
+        let rotation = [
+            [r[0][0], r[1][0], r[2][0]],
+            [r[0][1], r[1][1], r[2][1]],
+            [r[0][2], r[1][2], r[2][2]],
+        ];
+
+        let inverse = Self {
+            rotation,
+            translation: [0., 0., 0.],
+        };
+        let t = inverse.rotate(self.translation);
+
+        Self {
+            rotation,
+            translation: [-t[0], -t[1], -t[2]],
+        }
+    }
+
+    /// Returns the transform equivalent to applying `other` and then `self`.
+    pub fn then(&self, other: &Self) -> Self {
+        let a = &self.rotation;
+        let b = &other.rotation;
+        let mut rotation = [[0.; 3]; 3];
+
+        for row in 0..3 {
+            for col in 0..3 {
+                rotation[row][col] = (0..3).map(|k| a[row][k] * b[k][col]).sum();
+            }
+        }
+
+        Self {
+            rotation,
+            translation: self.apply(other.translation),
+        }
+    }
+}
+
+impl Pose {
+    /// Returns the transform from the frame described by this pose into its
+    /// parent frame.
+    pub fn transform(&self) -> Transform {
+        let (sin, cos) = self.angle.sin_cos();
+
+        Transform::from_euler(
+            self.yaw,
+            self.pitch,
+            self.roll,
+            [self.dist * cos, self.dist * sin, self.height],
+        )
+    }
+}
+
+impl VisionTarget {
+    /// Returns the position of the target in its frame of reference.
+    pub fn position(&self) -> Vector3 {
+        let (sin, cos) = self.theta.sin_cos();
+
+        [self.dist * cos, self.dist * sin, self.height]
+    }
+
+    /// Creates a target from the pose of its model in the camera frame.
+    ///
+    /// The model frame has `x` pointing right across the target's face, `y`
+    /// pointing up and `z` pointing out of the face towards the viewer.
+    pub fn from_pose(id: u16, pose: &Transform, confidence: f32) -> VisionTarget {
+        let [x, y, z] = pose.translation;
+        let theta = y.atan2(x);
+
+        // The direction into the target's face is along the model's -z axis.
+        let r = &pose.rotation;
+        let facing = (-r[1][2]).atan2(-r[0][2]);
+
+        VisionTarget {
+            id,
+            beta: normalize_angle(facing - theta),
+            theta,
+            dist: x.hypot(y),
+            height: z,
+            confidence,
+            ..VisionTarget::default()
+        }
+    }
+
+    /// Converts a target measured relative to `camera` into one measured
+    /// relative to the robot, using the camera's mount pose.
+    pub fn to_robot_frame(&self, camera: &CameraConfig) -> VisionTarget {
+        let [x, y, z] = camera.pose.transform().apply(self.position());
+        let theta = y.atan2(x);
+
+        // The direction the target's face points, measured in the robot frame.
+        let facing = self.theta + self.beta + camera.pose.yaw;
+
+        VisionTarget {
+            id: self.id,
+            beta: normalize_angle(facing - theta),
+            theta,
+            dist: x.hypot(y),
+            height: z,
+            ..self.clone()
+        }
+    }
+}
+
+/// Wraps an angle in radians into the range `(-PI, PI]`.
+pub fn normalize_angle(angle: f64) -> f64 {
+    let wrapped = (angle + PI).rem_euclid(2. * PI) - PI;
+
+    if wrapped == -PI {
+        PI
+    } else {
+        wrapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{a} != {b}");
+    }
+
+    #[test]
+    fn test_level_camera_offset() {
+        let camera = CameraConfig {
+            pose: Pose {
+                angle: FRAC_PI_2,
+                dist: 0.5,
+                height: 0.3,
+                ..Pose::default()
+            },
+            ..CameraConfig::default()
+        };
+
+        let target = VisionTarget {
+            dist: 2.,
+            height: 1.,
+            ..VisionTarget::default()
+        };
+        let robot = target.to_robot_frame(&camera);
+
+        assert_close(robot.theta, 0.5f64.atan2(2.));
+        assert_close(robot.dist, 2f64.hypot(0.5));
+        assert_close(robot.height, 1.3);
+        assert_close(robot.beta, -robot.theta);
+    }
+
+    #[test]
+    fn test_rotated_camera() {
+        let camera = CameraConfig {
+            pose: Pose {
+                yaw: FRAC_PI_2,
+                pitch: 0.2,
+                ..Pose::default()
+            },
+            ..CameraConfig::default()
+        };
+
+        let target = VisionTarget {
+            dist: 3.,
+            ..VisionTarget::default()
+        };
+        let robot = target.to_robot_frame(&camera);
+
+        assert_close(robot.theta, FRAC_PI_2);
+        assert_close(robot.dist, 3. * 0.2f64.cos());
+        assert_close(robot.height, 3. * 0.2f64.sin());
+    }
+
+    #[test]
+    fn test_transform_round_trip() {
+        let transform = Transform::from_euler(0.4, -0.3, 0.2, [1., -2., 0.5]);
+        let (yaw, pitch, roll) = transform.euler();
+
+        assert_close(yaw, 0.4);
+        assert_close(pitch, -0.3);
+        assert_close(roll, 0.2);
+
+        let point = [0.3, 0.7, -1.1];
+        let back = transform.inverse().apply(transform.apply(point));
+        for (a, b) in back.iter().zip(point.iter()) {
+            assert_close(*a, *b);
+        }
+    }
+}
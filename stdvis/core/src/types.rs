@@ -10,13 +10,23 @@ use serde::{Deserialize, Serialize};
 use crate::traits::ImageData;
 
 /// A representation of a relative position and rotation.
+///
+/// Positions are given in polar form on the floor plane plus a height, and
+/// rotations as yaw, pitch and roll applied in that order. See the
+/// [`transform`](crate::transform) module for the frame conventions.
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Pose {
+    /// The bearing of the position from the parent frame's origin, in radians.
     pub angle: f64,
+    /// The horizontal distance from the parent frame's origin.
     pub dist: f64,
+    /// The height above the parent frame's origin.
     pub height: f64,
+    /// The rotation about the vertical axis, in radians. Positive turns left.
     pub yaw: f64,
+    /// The rotation about the lateral axis, in radians. Positive tilts up.
     pub pitch: f64,
+    /// The rotation about the forward axis, in radians.
     pub roll: f64,
 }
 
@@ -29,19 +39,25 @@ pub struct CameraConfig {
     pub fov: (f64, f64),
     pub intrinsic_matrix: Array2<f64>,
     pub distortion_coeffs: Array1<f64>,
+    /// The RMS reprojection error of the camera's calibration, in pixels.
+    #[serde(default)]
+    pub reprojection_error: f64,
 }
 
 /// An image, backed by a generic image data type `I`.
 pub struct Image<'src, Storage: ImageData> {
     pub timestamp: Instant,
+    /// The number of frames captured by the camera before this one.
+    pub sequence: u32,
     pub camera: &'src CameraConfig,
     pub pixels: Storage,
 }
 
 impl<'src, I: ImageData> Image<'src, I> {
-    pub fn new(timestamp: Instant, camera: &'src CameraConfig, pixels: I) -> Self {
+    pub fn new(timestamp: Instant, sequence: u32, camera: &'src CameraConfig, pixels: I) -> Self {
         Self {
             timestamp,
+            sequence,
             camera,
             pixels,
         }
@@ -63,25 +79,68 @@ impl<'src, I: ImageData> DerefMut for Image<'src, I> {
 }
 
 /// A collection of points that form a contour.
-#[derive(Debug)]
+///
+/// Geometric measurements are provided by the [`geometry`](crate::geometry)
+/// module.
+#[derive(Clone, Debug, PartialEq)]
 pub struct Contour {
     pub points: Vec<(f32, f32)>,
 }
 
+/// The decoded identity of a fiducial marker, such as an AprilTag.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Fiducial {
+    /// The name of the marker family, such as `tag36h11`.
+    pub family: String,
+    /// The number of bits corrected while decoding the marker, if the
+    /// detector reports it.
+    pub hamming: Option<u8>,
+    /// How far the decoded bits were from the decision threshold, where larger
+    /// values are more certain, if the detector reports it.
+    pub decision_margin: Option<f32>,
+}
+
 /// A collection of contours that form a logical group.
+///
+/// Groups made from fiducial markers hold the marker's decoded id as their
+/// `id`, and its four corners as a single contour. Other groups are numbered
+/// afresh in each frame, so their ids don't follow a target across frames.
 #[derive(Debug)]
 pub struct ContourGroup<'src> {
-    pub id: u8,
+    pub id: u16,
     pub camera: &'src CameraConfig,
     pub contours: Vec<Contour>,
+    pub fiducial: Option<Fiducial>,
 }
 
+/// A target measured relative to some frame of reference.
+///
+/// Targets produced by an analyzer are relative to the camera that saw them;
+/// use [`VisionTarget::to_robot_frame`] to make them relative to the robot.
 #[derive(Clone, Debug, Default, Serialize, Deserialize, MinCodec, PartialEq)]
 pub struct VisionTarget {
-    pub id: u8,
+    pub id: u16,
+    /// The yaw of the target's face relative to the line of sight, in radians.
     pub beta: f64,
+    /// The bearing of the target from the frame's forward axis, in radians.
+    /// Positive values are to the left.
     pub theta: f64,
+    /// The horizontal distance to the target.
     pub dist: f64,
+    /// The height of the target above the frame's origin.
     pub height: f64,
     pub confidence: f32,
+    /// The sequence number of the frame the target was seen in.
+    #[serde(default)]
+    pub frame: u32,
+    /// When the frame was captured, in microseconds since the Unix epoch.
+    #[serde(default)]
+    pub capture_time: u64,
+    /// The time from capture until the target was measured, in microseconds.
+    #[serde(default)]
+    pub latency: u32,
+    /// The id of the track following the target across frames, or 0 if it
+    /// isn't tracked.
+    #[serde(default)]
+    pub track: u32,
 }
@@ -0,0 +1,180 @@
+//! Estimates of how precisely a `VisionTarget` was measured, for weighting
+//! vision measurements against other sensors.
+//!
+//! Uncertainty is kept alongside a target rather than in it, so that the wire
+//! form of `VisionTarget` stays compact.
+
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::types::{CameraConfig, ContourGroup, VisionTarget};
+
+/// The uncertainty of a target's `theta` and `dist`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Uncertainty {
+    /// The covariance of `theta` (in radians) and `dist`, in that order.
+    pub covariance: [[f64; 2]; 2],
+}
+
+impl Uncertainty {
+    /// Creates an uncertainty from independent standard deviations.
+    pub fn from_std_devs(theta: f64, dist: f64) -> Self {
+        Self {
+            covariance: [[theta * theta, 0.], [0., dist * dist]],
+        }
+    }
+
+    /// The standard deviation of `theta`, in radians.
+    pub fn theta_std_dev(&self) -> f64 {
+        self.covariance[0][0].sqrt()
+    }
+
+    /// The standard deviation of `dist`.
+    pub fn dist_std_dev(&self) -> f64 {
+        self.covariance[1][1].sqrt()
+    }
+
+    /// Returns the covariance of the target's position `(x, y)` on the floor
+    /// plane of its frame.
+    pub fn position_covariance(&self, target: &VisionTarget) -> [[f64; 2]; 2] {
+        let (sin, cos) = target.theta.sin_cos();
+        let jacobian = [[-target.dist * sin, cos], [target.dist * cos, sin]];
+
+        congruence(&jacobian, &self.covariance)
+    }
+
+    /// Creates the uncertainty of a target from the covariance of its
+    /// position `(x, y)` on the floor plane.
+    pub fn from_position_covariance(target: &VisionTarget, covariance: &[[f64; 2]; 2]) -> Self {
+        let (sin, cos) = target.theta.sin_cos();
+        let dist = target.dist.max(f64::EPSILON);
+        let inverse_jacobian = [[-sin / dist, cos / dist], [cos, sin]];
+
+        Self {
+            covariance: congruence(&inverse_jacobian, covariance),
+        }
+    }
+
+    /// Converts the uncertainty of a target measured relative to `camera`
+    /// into the robot frame, matching [`VisionTarget::to_robot_frame`].
+    pub fn to_robot_frame(&self, target: &VisionTarget, camera: &CameraConfig) -> Uncertainty {
+        // The target's height is fixed, so its floor position in the robot
+        // frame only depends on its floor position in the camera frame.
+        let r = camera.pose.transform().rotation;
+        let rotation = [[r[0][0], r[0][1]], [r[1][0], r[1][1]]];
+        let covariance = congruence(&rotation, &self.position_covariance(target));
+
+        Self::from_position_covariance(&target.to_robot_frame(camera), &covariance)
+    }
+}
+
+/// The sources of noise in a measurement.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NoiseModel {
+    /// The standard deviation of each corner's position, in pixels, not
+    /// counting calibration error.
+    #[serde(default = "NoiseModel::default_pixel_noise")]
+    pub pixel_noise: f64,
+}
+
+impl NoiseModel {
+    fn default_pixel_noise() -> f64 {
+        0.5
+    }
+
+    /// The standard deviation of each corner's position in `camera`'s images,
+    /// in pixels, combining `pixel_noise` with the camera's calibration
+    /// residual.
+    pub fn corner_noise(&self, camera: &CameraConfig) -> f64 {
+        self.pixel_noise.hypot(camera.reprojection_error)
+    }
+
+    /// The standard deviation of the center of a group along each image
+    /// axis, in pixels.
+    ///
+    /// Each point of the group's contours is treated as a corner with
+    /// independent noise, so the center's noise shrinks with the number of
+    /// corners. How this translates into bearing and distance depends on the
+    /// analyzer, which estimates its own `Uncertainty`.
+    pub fn center_noise(&self, group: &ContourGroup) -> Result<f64> {
+        let corners = group
+            .contours
+            .iter()
+            .map(|contour| contour.points.len())
+            .sum::<usize>();
+
+        ensure!(corners > 0, "group {} has no corners to measure", group.id);
+
+        Ok(self.corner_noise(group.camera) / (corners as f64).sqrt())
+    }
+}
+
+impl Default for NoiseModel {
+    fn default() -> Self {
+        Self {
+            pixel_noise: Self::default_pixel_noise(),
+        }
+    }
+}
+
+/// Returns `a * m * a^T`.
+pub(crate) fn congruence(a: &[[f64; 2]; 2], m: &[[f64; 2]; 2]) -> [[f64; 2]; 2] {
+    let mut out = [[0.; 2]; 2];
+
+    for (row, out) in out.iter_mut().enumerate() {
+        for (col, out) in out.iter_mut().enumerate() {
+            *out = (0..2)
+                .flat_map(|i| (0..2).map(move |j| (i, j)))
+                .map(|(i, j)| a[row][i] * m[i][j] * a[col][j])
+                .sum();
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::FRAC_PI_2;
+
+    use super::*;
+    use crate::types::{Contour, Pose};
+
+    #[test]
+    fn test_uncertainty() {
+        let camera = CameraConfig {
+            pose: Pose {
+                yaw: FRAC_PI_2,
+                ..Pose::default()
+            },
+            reprojection_error: 0.5,
+            ..CameraConfig::default()
+        };
+        let group = ContourGroup {
+            id: 0,
+            camera: &camera,
+            contours: vec![Contour::new(vec![
+                (300., 220.),
+                (320., 220.),
+                (320., 240.),
+                (300., 240.),
+            ])],
+            fiducial: None,
+        };
+
+        let noise = NoiseModel::default();
+        assert!((noise.corner_noise(&camera) - 0.5f64.hypot(0.5)).abs() < 1e-12);
+        assert!((noise.center_noise(&group).unwrap() - 0.5f64.hypot(0.5) / 2.).abs() < 1e-12);
+
+        let target = VisionTarget {
+            dist: 2.,
+            ..VisionTarget::default()
+        };
+        let uncertainty = Uncertainty::from_std_devs(0.0005, 0.05);
+
+        // Turning the camera doesn't change the size of the uncertainty.
+        let robot = uncertainty.to_robot_frame(&target, &camera);
+        assert!((robot.theta_std_dev() - 0.0005).abs() < 1e-9);
+        assert!((robot.dist_std_dev() - 0.05).abs() < 1e-9);
+    }
+}
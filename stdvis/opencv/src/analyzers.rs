@@ -0,0 +1,7 @@
+mod pnp;
+
+pub use self::pnp::{
+    Disambiguation, PlanarSolutions, PnpAnalyzer, PnpConfig, PnpMethod, PnpSolution,
+};
+
+pub(crate) use self::pnp::{pose_to_vecs, solution_from_vecs, solution_with_errors};
@@ -0,0 +1,630 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::{bail, ensure, Context, Result};
+use opencv::{
+    calib3d,
+    core::{self, no_array, Point2f, Point3f, Vector, DECOMP_SVD},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use stdvis_core::{
+    geometry::{order_corners, Point},
+    model::{ModelRef, TargetModel},
+    traits::ContourAnalyzer,
+    transform::{normalize_angle, Transform},
+    types::{ContourGroup, VisionTarget},
+    uncertainty::{NoiseModel, Uncertainty},
+};
+
+use crate::convert::intrinsics_to_mats;
+
+/// The change of basis from OpenCV's camera axes (`x` right, `y` down, `z`
+/// forward) to stdvis's camera axes (`x` forward, `y` left, `z` up).
+const CV_TO_CAMERA: [[f64; 3]; 3] = [[0., 0., 1.], [-1., 0., 0.], [0., -1., 0.]];
+
+/// The algorithm used to solve for the target's pose.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum PnpMethod {
+    #[default]
+    Iterative,
+    Epnp,
+    /// Only valid for planar models. Both of the poses which fit a planar
+    /// target are found, and one is chosen by the configured
+    /// [`Disambiguation`].
+    Ippe,
+}
+
+/// How to choose between the two poses which fit a planar target.
+///
+/// A small or distant planar target looks almost the same when tilted either
+/// way about an axis in its face, so IPPE returns a pose for each tilt.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum Disambiguation {
+    /// Choose the pose with the lower reprojection error.
+    #[default]
+    ReprojectionError,
+    /// Choose the pose whose facing is closest to that chosen for the same
+    /// marker in the previous frame.
+    ///
+    /// Only fiducial groups, whose ids are their markers' decoded ids, are
+    /// accepted. Other groups are numbered afresh in each frame, so their
+    /// ids can't be matched across frames.
+    Temporal,
+    /// Choose the pose whose facing is closest to the prior set with
+    /// [`PnpAnalyzer::set_facing_prior`].
+    HeadingPrior,
+}
+
+impl PnpMethod {
+    pub(crate) fn cv_flag(self) -> i32 {
+        match self {
+            PnpMethod::Iterative => calib3d::SOLVEPNP_ITERATIVE,
+            PnpMethod::Epnp => calib3d::SOLVEPNP_EPNP,
+            PnpMethod::Ippe => calib3d::SOLVEPNP_IPPE,
+        }
+    }
+}
+
+/// Parameters for a `PnpAnalyzer`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PnpConfig {
+    /// The model of the target whose corners are matched.
+    pub model: ModelRef,
+    #[serde(default)]
+    pub method: PnpMethod,
+    /// The largest RMS reprojection error, in pixels, for a solution to be
+    /// accepted. Confidence falls linearly from 1 to 0 as the error
+    /// approaches this limit.
+    #[serde(default = "PnpConfig::default_max_reprojection_error")]
+    pub max_reprojection_error: f64,
+    /// How to choose between the poses found by [`PnpMethod::Ippe`].
+    #[serde(default)]
+    pub disambiguation: Disambiguation,
+}
+
+impl PnpConfig {
+    fn default_max_reprojection_error() -> f64 {
+        8.
+    }
+}
+
+/// The result of solving for a target's pose.
+#[derive(Clone, Debug)]
+pub struct PnpSolution {
+    /// The transform from the target's model frame into the camera frame.
+    pub pose: Transform,
+    /// The rotation of the model in OpenCV's camera axes, as a Rodrigues
+    /// vector.
+    pub rvec: [f64; 3],
+    /// The translation of the model in OpenCV's camera axes.
+    pub tvec: [f64; 3],
+    /// The RMS distance, in pixels, between the observed corners and the
+    /// model's corners projected with this solution.
+    pub reprojection_error: f64,
+}
+
+impl PnpSolution {
+    /// Returns the direction the target's face points in the camera frame.
+    pub fn facing(&self) -> f64 {
+        let r = &self.pose.rotation;
+        (-r[1][2]).atan2(-r[0][2])
+    }
+}
+
+/// Every pose which fits a planar target, and which one was chosen.
+#[derive(Clone, Debug)]
+pub struct PlanarSolutions {
+    /// The candidate poses, from lowest to highest reprojection error.
+    pub candidates: Vec<PnpSolution>,
+    /// The index of the chosen candidate.
+    pub chosen: usize,
+    /// How uncertain the choice is, from 0 when one candidate is clearly
+    /// better to 1 when they are indistinguishable.
+    pub ambiguity: f64,
+}
+
+impl PlanarSolutions {
+    pub fn solution(&self) -> &PnpSolution {
+        &self.candidates[self.chosen]
+    }
+}
+
+/// A `ContourAnalyzer` which matches a group's corners to a 3D model of the
+/// target and solves for the target's full pose.
+pub struct PnpAnalyzer {
+    config: PnpConfig,
+    model: TargetModel,
+    /// The facing chosen for each fiducial id in the previous frame.
+    history: Mutex<HashMap<u16, f64>>,
+    /// The expected facing of the target in the robot frame.
+    facing_prior: Mutex<Option<f64>>,
+}
+
+impl PnpAnalyzer {
+    pub fn new(config: PnpConfig) -> Result<Self> {
+        let model = config.model.resolve()?;
+
+        if let PnpMethod::Ippe = config.method {
+            ensure!(
+                model.is_planar(),
+                "IPPE requires a planar model, but {} is not planar",
+                model.name
+            );
+        }
+
+        Ok(Self {
+            config,
+            model,
+            history: Mutex::new(HashMap::new()),
+            facing_prior: Mutex::new(None),
+        })
+    }
+
+    pub fn config(&self) -> &PnpConfig {
+        &self.config
+    }
+
+    pub fn model(&self) -> &TargetModel {
+        &self.model
+    }
+
+    /// Finds the image corners of each piece of the target, ordered to match
+    /// the model.
+    pub fn image_corners(&self, group: &ContourGroup) -> Result<Vec<Point>> {
+        let pieces = &self.model.pieces;
+
+        ensure!(
+            group.contours.len() == pieces.len(),
+            "expected {} contours in group {}, found {}",
+            pieces.len(),
+            group.id,
+            group.contours.len()
+        );
+
+        let mut contours = group.contours.iter().collect::<Vec<_>>();
+        contours.sort_by(|a, b| {
+            let (a, b) = (
+                a.centroid().unwrap_or_default(),
+                b.centroid().unwrap_or_default(),
+            );
+            a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let mut corners = Vec::with_capacity(self.model.point_count());
+
+        // A camera rolled clockwise sees the target rotated counter-clockwise.
+        let rotation = -group.camera.pose.roll as f32;
+
+        for (contour, piece) in contours.into_iter().zip(pieces) {
+            let count = piece.corners.len();
+
+            match contour.corners(count) {
+                Some(piece_corners) => corners.extend(order_corners(piece_corners, rotation)),
+                None => bail!(
+                    "failed to find {count} corners on a contour in group {}",
+                    group.id
+                ),
+            }
+        }
+
+        Ok(corners)
+    }
+
+    /// Sets the direction the target's face is expected to point in the robot
+    /// frame, used by [`Disambiguation::HeadingPrior`]. This is the target's
+    /// known facing on the field minus the robot's heading.
+    pub fn set_facing_prior(&self, facing: Option<f64>) {
+        *self.facing_prior.lock().unwrap() = facing;
+    }
+
+    /// Forgets the facings chosen in previous frames.
+    pub fn reset_history(&self) {
+        self.history.lock().unwrap().clear();
+    }
+
+    /// Solves for the pose of the target described by `group`.
+    pub fn solve(&self, group: &ContourGroup) -> Result<PnpSolution> {
+        let (object_points, image_points, camera_matrix, dist_coeffs) =
+            self.correspondences(group)?;
+
+        let mut rvec = Mat::default();
+        let mut tvec = Mat::default();
+
+        let solved = calib3d::solve_pnp(
+            &object_points,
+            &image_points,
+            &camera_matrix,
+            &dist_coeffs,
+            &mut rvec,
+            &mut tvec,
+            false,
+            self.config.method.cv_flag(),
+        )
+        .context("solving PnP")?;
+
+        if !solved {
+            bail!("no PnP solution found for group {}", group.id);
+        }
+
+        solution_from_vecs(
+            &object_points,
+            &image_points,
+            &camera_matrix,
+            &dist_coeffs,
+            &rvec,
+            &tvec,
+        )
+    }
+
+    /// Finds both poses which fit a planar target with IPPE, and chooses one
+    /// using the configured [`Disambiguation`].
+    pub fn solve_planar(&self, group: &ContourGroup) -> Result<PlanarSolutions> {
+        if let Disambiguation::Temporal = self.config.disambiguation {
+            ensure!(
+                group.fiducial.is_some(),
+                "temporal disambiguation requires fiducial groups, but group {} has no fiducial",
+                group.id
+            );
+        }
+
+        let (object_points, image_points, camera_matrix, dist_coeffs) =
+            self.correspondences(group)?;
+
+        let mut rvecs = Vector::<Mat>::new();
+        let mut tvecs = Vector::<Mat>::new();
+
+        let count = calib3d::solve_pnp_generic(
+            &object_points,
+            &image_points,
+            &camera_matrix,
+            &dist_coeffs,
+            &mut rvecs,
+            &mut tvecs,
+            false,
+            calib3d::SolvePnPMethod::SOLVEPNP_IPPE,
+            &no_array(),
+            &no_array(),
+            &mut no_array(),
+        )
+        .context("solving planar PnP")?;
+
+        let mut candidates = (0..count.max(0) as usize)
+            .map(|idx| {
+                solution_from_vecs(
+                    &object_points,
+                    &image_points,
+                    &camera_matrix,
+                    &dist_coeffs,
+                    &rvecs.get(idx)?,
+                    &tvecs.get(idx)?,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if candidates.is_empty() {
+            bail!("no planar PnP solution found for group {}", group.id);
+        }
+
+        candidates.sort_by(|a, b| {
+            a.reprojection_error
+                .partial_cmp(&b.reprojection_error)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let (chosen, ambiguity) = self.disambiguate(group, &candidates);
+
+        if let Disambiguation::Temporal = self.config.disambiguation {
+            self.history
+                .lock()
+                .unwrap()
+                .insert(group.id, candidates[chosen].facing());
+        }
+
+        Ok(PlanarSolutions {
+            candidates,
+            chosen,
+            ambiguity,
+        })
+    }
+
+    /// Chooses between candidates sorted by reprojection error, returning the
+    /// chosen index and the remaining ambiguity.
+    fn disambiguate(&self, group: &ContourGroup, candidates: &[PnpSolution]) -> (usize, f64) {
+        let (best, other) = match candidates {
+            [best, other, ..] => (best, other),
+            _ => return (0, 0.),
+        };
+
+        // Equal errors are fully ambiguous, and the ratio falls towards zero as
+        // the second candidate fits worse.
+        let mut ambiguity = if other.reprojection_error > 0. {
+            (best.reprojection_error / other.reprojection_error).clamp(0., 1.)
+        } else {
+            1.
+        };
+
+        let prior = match self.config.disambiguation {
+            Disambiguation::ReprojectionError => None,
+            Disambiguation::Temporal => self.history.lock().unwrap().get(&group.id).copied(),
+            Disambiguation::HeadingPrior => self
+                .facing_prior
+                .lock()
+                .unwrap()
+                .map(|facing| facing - group.camera.pose.yaw),
+        };
+
+        let prior = match prior {
+            Some(prior) => prior,
+            None => return (0, ambiguity),
+        };
+
+        let offsets =
+            [best, other].map(|candidate| normalize_angle(candidate.facing() - prior).abs());
+        let chosen = if offsets[1] < offsets[0] { 1 } else { 0 };
+
+        // The prior settles the choice to the extent that it is closer to one
+        // candidate than the other, relative to how far apart they are.
+        let spread = normalize_angle(best.facing() - other.facing()).abs();
+        if spread > 0. {
+            let decisiveness = ((offsets[1 - chosen] - offsets[chosen]) / spread).clamp(0., 1.);
+            ambiguity *= 1. - decisiveness;
+        }
+
+        (chosen, ambiguity)
+    }
+
+    /// Matches the group's image corners to the model's points, and converts
+    /// them and the camera's intrinsics for OpenCV.
+    fn correspondences(
+        &self,
+        group: &ContourGroup,
+    ) -> Result<(Vector<Point3f>, Vector<Point2f>, Mat, Mat)> {
+        let image_points = self
+            .image_corners(group)?
+            .into_iter()
+            .map(|(x, y)| Point2f::new(x, y))
+            .collect::<Vector<_>>();
+
+        let object_points = self
+            .model
+            .points()
+            .map(|[x, y, z]| Point3f::new(x as f32, y as f32, z as f32))
+            .collect::<Vector<_>>();
+
+        let (camera_matrix, dist_coeffs) =
+            intrinsics_to_mats(group.camera).context("converting camera intrinsics")?;
+
+        Ok((object_points, image_points, camera_matrix, dist_coeffs))
+    }
+
+    /// Measures the target described by `group`, returning it along with the
+    /// solution it was measured from.
+    pub fn measure(&self, group: &ContourGroup) -> Result<(VisionTarget, PnpSolution)> {
+        let (solution, ambiguity) = match self.config.method {
+            PnpMethod::Ippe => {
+                let solutions = self.solve_planar(group)?;
+                (solutions.solution().clone(), solutions.ambiguity)
+            }
+            _ => (self.solve(group)?, 0.),
+        };
+
+        ensure!(
+            solution.reprojection_error <= self.config.max_reprojection_error,
+            "reprojection error of {} px exceeds limit for group {}",
+            solution.reprojection_error,
+            group.id
+        );
+
+        let target = VisionTarget::from_pose(
+            group.id,
+            &solution.pose,
+            self.confidence(&solution) * (1. - ambiguity) as f32,
+        );
+
+        Ok((target, solution))
+    }
+
+    /// Estimates the uncertainty of a target measured from `group` with
+    /// `solution`, from the sensitivity of the model's projection to its pose.
+    ///
+    /// Each corner is taken to have the larger of the noise model's corner
+    /// noise and the solution's RMS reprojection error.
+    pub fn uncertainty(
+        &self,
+        noise: &NoiseModel,
+        group: &ContourGroup,
+        solution: &PnpSolution,
+    ) -> Result<Uncertainty> {
+        let (object_points, _, camera_matrix, dist_coeffs) = self.correspondences(group)?;
+
+        let mut projected = Vector::<Point2f>::new();
+        let mut jacobian = Mat::default();
+        calib3d::project_points(
+            &object_points,
+            &Mat::from_slice(&solution.rvec[..])?,
+            &Mat::from_slice(&solution.tvec[..])?,
+            &camera_matrix,
+            &dist_coeffs,
+            &mut projected,
+            &mut jacobian,
+            0.,
+        )
+        .context("projecting model points")?;
+
+        // The columns of the Jacobian start with the rotation and translation
+        // vectors, followed by the intrinsics, which are held fixed.
+        let mut information = [[0.; 6]; 6];
+        for row in 0..jacobian.rows() {
+            let row = jacobian.at_row::<f64>(row)?;
+
+            for (i, info) in information.iter_mut().enumerate() {
+                for (j, info) in info.iter_mut().enumerate() {
+                    *info += row[i] * row[j];
+                }
+            }
+        }
+
+        let mut covariance = Mat::default();
+        core::invert(
+            &Mat::from_slice_2d(&information)?,
+            &mut covariance,
+            DECOMP_SVD,
+        )
+        .context("inverting pose information")?;
+
+        let pixel_noise = noise
+            .corner_noise(group.camera)
+            .max(solution.reprojection_error);
+        let variance = pixel_noise * pixel_noise;
+        let translation = |i: i32, j: i32| -> Result<f64> {
+            Ok(variance * *covariance.at_2d::<f64>(i + 3, j + 3)?)
+        };
+
+        // The target's floor position is `(tz, -tx)` in OpenCV's axes.
+        let [tx, _, tz] = solution.tvec;
+        let position = [
+            [translation(2, 2)?, -translation(2, 0)?],
+            [-translation(0, 2)?, translation(0, 0)?],
+        ];
+        let target = VisionTarget {
+            theta: (-tx).atan2(tz),
+            dist: tz.hypot(tx),
+            ..VisionTarget::default()
+        };
+
+        Ok(Uncertainty::from_position_covariance(&target, &position))
+    }
+
+    fn confidence(&self, solution: &PnpSolution) -> f32 {
+        (1. - solution.reprojection_error / self.config.max_reprojection_error).clamp(0., 1.) as f32
+    }
+}
+
+impl ContourAnalyzer for PnpAnalyzer {
+    fn analyze(&self, contours: &ContourGroup) -> Result<VisionTarget> {
+        let (target, _) = self.measure(contours)?;
+        Ok(target)
+    }
+
+    fn analyze_with_pose(
+        &self,
+        contours: &ContourGroup,
+    ) -> Result<(VisionTarget, Option<Transform>)> {
+        let (target, solution) = self.measure(contours)?;
+        Ok((target, Some(solution.pose)))
+    }
+}
+
+/// Converts a pose of the model in the camera frame back into OpenCV's
+/// rotation and translation vectors.
+pub(crate) fn pose_to_vecs(pose: &Transform) -> Result<(Mat, Mat)> {
+    let basis = Transform {
+        rotation: CV_TO_CAMERA,
+        translation: [0., 0., 0.],
+    };
+    let cv_pose = basis.inverse().then(pose);
+
+    let mut rvec = Mat::default();
+    calib3d::rodrigues(
+        &Mat::from_slice_2d(&cv_pose.rotation)?,
+        &mut rvec,
+        &mut no_array(),
+    )
+    .context("converting rotation matrix")?;
+    let tvec = Mat::from_slice(&cv_pose.translation[..])?;
+
+    Ok((rvec, tvec))
+}
+
+/// Builds a `PnpSolution` from OpenCV's rotation and translation vectors,
+/// computing the reprojection error of the model's points.
+pub(crate) fn solution_from_vecs(
+    object_points: &Vector<Point3f>,
+    image_points: &Vector<Point2f>,
+    camera_matrix: &Mat,
+    dist_coeffs: &Mat,
+    rvec: &Mat,
+    tvec: &Mat,
+) -> Result<PnpSolution> {
+    let (solution, _) = solution_with_errors(
+        object_points,
+        image_points,
+        camera_matrix,
+        dist_coeffs,
+        rvec,
+        tvec,
+    )?;
+
+    Ok(solution)
+}
+
+/// Like [`solution_from_vecs`], but also returns the squared reprojection
+/// error of each point.
+pub(crate) fn solution_with_errors(
+    object_points: &Vector<Point3f>,
+    image_points: &Vector<Point2f>,
+    camera_matrix: &Mat,
+    dist_coeffs: &Mat,
+    rvec: &Mat,
+    tvec: &Mat,
+) -> Result<(PnpSolution, Vec<f64>)> {
+    let mut projected = Vector::<Point2f>::new();
+    calib3d::project_points(
+        object_points,
+        rvec,
+        tvec,
+        camera_matrix,
+        dist_coeffs,
+        &mut projected,
+        &mut no_array(),
+        0.,
+    )
+    .context("projecting model points")?;
+
+    let squared_errors = image_points
+        .iter()
+        .zip(projected.iter())
+        .map(|(observed, projected)| {
+            let (dx, dy) = (observed.x - projected.x, observed.y - projected.y);
+            (dx * dx + dy * dy) as f64
+        })
+        .collect::<Vec<_>>();
+    let reprojection_error =
+        (squared_errors.iter().sum::<f64>() / image_points.len().max(1) as f64).sqrt();
+
+    let mut rotation_mat = Mat::default();
+    calib3d::rodrigues(rvec, &mut rotation_mat, &mut no_array())
+        .context("converting rotation vector")?;
+
+    let mut rotation = [[0.; 3]; 3];
+    for (row, values) in rotation.iter_mut().enumerate() {
+        for (col, value) in values.iter_mut().enumerate() {
+            *value = *rotation_mat.at_2d::<f64>(row as i32, col as i32)?;
+        }
+    }
+
+    let mut rvec_out = [0.; 3];
+    let mut tvec_out = [0.; 3];
+    for (idx, (r, t)) in rvec_out.iter_mut().zip(tvec_out.iter_mut()).enumerate() {
+        *r = *rvec.at::<f64>(idx as i32)?;
+        *t = *tvec.at::<f64>(idx as i32)?;
+    }
+
+    let basis = Transform {
+        rotation: CV_TO_CAMERA,
+        translation: [0., 0., 0.],
+    };
+    let cv_pose = Transform {
+        rotation,
+        translation: tvec_out,
+    };
+
+    let solution = PnpSolution {
+        pose: basis.then(&cv_pose),
+        rvec: rvec_out,
+        tvec: tvec_out,
+        reprojection_error,
+    };
+
+    Ok((solution, squared_errors))
+}
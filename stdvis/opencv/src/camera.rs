@@ -1,4 +1,4 @@
-use std::io;
+use std::{io, time::Instant};
 
 use ndarray::{ArrayViewD, ArrayViewMutD};
 use opencv::{core::Vector, prelude::*, videoio::*};
@@ -14,6 +14,8 @@ use opencv::{
     cudacodec::{create_video_reader, VideoReader},
 };
 
+use tracing::{debug, info, warn};
+
 use crate::convert::AsArrayView;
 
 pub struct MatImageData {
@@ -56,6 +58,12 @@ pub struct OcvCamera {
 
     #[cfg(feature = "cuda")]
     video_source: Ptr<dyn VideoReader>,
+
+    frame_count: u32,
+
+    /// The driver's timestamp of the previous frame, in milliseconds.
+    #[cfg(not(any(feature = "cuda")))]
+    driver_time: Option<f64>,
 }
 
 impl OcvCamera {
@@ -92,10 +100,20 @@ impl OcvCamera {
         let video_source = create_video_reader(&device_path, &Vector::from_slice(params), false)
             .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
 
+        info!(
+            camera = config.id,
+            width = config.resolution.0,
+            height = config.resolution.1,
+            "opened camera"
+        );
+
         Ok(Self {
             device,
             config,
             video_source,
+            frame_count: 0,
+            #[cfg(not(any(feature = "cuda")))]
+            driver_time: None,
         })
     }
 
@@ -121,6 +139,8 @@ impl OcvCamera {
         self.device
             .set_control(V4L2_CID_EXPOSURE_ABSOLUTE, Control::Value(exposure))?;
 
+        info!(camera = self.config.id, exposure, "set exposure");
+
         Ok(())
     }
 }
@@ -138,19 +158,38 @@ impl Camera for OcvCamera {
         #[cfg(feature = "cuda")]
         let mut gpu_mat = GpuMat::default().expect("initializing GpuMat");
 
+        // The frame is timestamped once it is grabbed, before it is decoded.
         #[cfg(not(any(feature = "cuda")))]
-        let success = self
-            .video_source
-            .read(&mut mat)
-            .expect("reading from VideoCapture");
+        let (success, timestamp) = {
+            let grabbed = self
+                .video_source
+                .grab()
+                .expect("grabbing from VideoCapture");
+            let timestamp = Instant::now();
+
+            let success = grabbed
+                && self
+                    .video_source
+                    .retrieve(&mut mat, 0)
+                    .expect("retrieving from VideoCapture");
+
+            (success, timestamp)
+        };
 
+        // The reader decodes as it grabs, so the timestamp includes decoding.
         #[cfg(feature = "cuda")]
-        let success = self
-            .video_source
-            .next_frame(&mut gpu_mat, &mut Stream::null().unwrap())
-            .expect("reading from VideoReader");
+        let (success, timestamp) = {
+            let success = self
+                .video_source
+                .next_frame(&mut gpu_mat, &mut Stream::null().unwrap())
+                .expect("reading from VideoReader");
+
+            (success, Instant::now())
+        };
 
         if !success {
+            warn!(camera = self.config.id, "failed to read frame");
+
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 format!(
@@ -165,16 +204,52 @@ impl Camera for OcvCamera {
             .download(&mut mat)
             .expect("downloading GpuMat to Mat");
 
-        // TODO: timestamp should be taken between grab() and retrieve() calls
+        // Frames the driver dropped while the pipeline was busy leave a gap in
+        // the sequence, found from the driver's capture timestamps.
+        #[cfg(not(any(feature = "cuda")))]
+        let skipped = {
+            let driver_time = self.video_source.get(CAP_PROP_POS_MSEC).unwrap_or_default();
+            let fps = self.video_source.get(CAP_PROP_FPS).unwrap_or_default();
+
+            let skipped = match self.driver_time.replace(driver_time) {
+                Some(last) => frame_gap(last, driver_time, fps) - 1,
+                None => 0,
+            };
+            if skipped > 0 {
+                debug!(camera = self.config.id, skipped, "driver dropped frames");
+            }
+
+            skipped
+        };
+
+        #[cfg(feature = "cuda")]
+        let skipped = 0;
+
+        let sequence = self.frame_count.wrapping_add(skipped);
+        self.frame_count = sequence.wrapping_add(1);
 
         Ok(Image::new(
-            std::time::Instant::now(),
+            timestamp,
+            sequence,
             self.config(),
             MatImageData::new(mat),
         ))
     }
 }
 
+/// Returns how many frame intervals apart two driver timestamps, in
+/// milliseconds, are. At least one is counted, as when the driver gives no
+/// timestamps or frame rate.
+#[cfg_attr(feature = "cuda", allow(dead_code))]
+fn frame_gap(last: f64, now: f64, fps: f64) -> u32 {
+    if fps <= 0. || now <= last {
+        return 1;
+    }
+
+    let intervals = (now - last) * fps / 1000.;
+    intervals.round().clamp(1., u32::MAX as f64) as u32
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs::File;
@@ -184,6 +259,18 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_frame_gap() {
+        // At 30 fps, frames arrive every 33.3 ms.
+        assert_eq!(frame_gap(1000., 1033.3, 30.), 1);
+        assert_eq!(frame_gap(1000., 1040., 30.), 1);
+        assert_eq!(frame_gap(1000., 1100., 30.), 3);
+
+        // Without usable timestamps, no frames are taken to be dropped.
+        assert_eq!(frame_gap(0., 0., 30.), 1);
+        assert_eq!(frame_gap(1000., 1100., 0.), 1);
+    }
+
     #[test]
     fn test_mat_pixel_extraction() {
         use crate::convert::AsMatView;
@@ -203,7 +290,7 @@ mod tests {
         let cv_image = MatImageData::new(imgcodecs::imread(PATH, imgcodecs::IMREAD_COLOR).unwrap());
 
         let config = CameraConfig::default();
-        let image = Image::new(std::time::Instant::now(), &config, cv_image);
+        let image = Image::new(std::time::Instant::now(), 0, &config, cv_image);
 
         let cv_pixels = image.as_pixels();
         let cv_raw = &image.as_mat_view();
@@ -6,7 +6,10 @@ use std::{
 
 use ndarray::{prelude::*, RawData};
 use opencv::prelude::*;
-use stdvis_core::{traits::ImageData, types::Image};
+use stdvis_core::{
+    traits::ImageData,
+    types::{CameraConfig, Image},
+};
 
 pub trait AsArrayView {
     fn as_array_view<Data: DataType>(&self) -> ArrayViewD<Data>;
@@ -137,4 +140,19 @@ impl<'src, I: ImageData> AsMatView for Image<'src, I> {
     }
 }
 
+/// Converts a camera's intrinsic matrix and distortion coefficients into the
+/// `Mat`s expected by OpenCV's calibration functions.
+pub fn intrinsics_to_mats(config: &CameraConfig) -> opencv::Result<(Mat, Mat)> {
+    let rows = config
+        .intrinsic_matrix
+        .outer_iter()
+        .map(|row| row.to_vec())
+        .collect::<Vec<_>>();
+
+    let camera_matrix = Mat::from_slice_2d(&rows)?;
+    let dist_coeffs = Mat::from_slice(&config.distortion_coeffs.to_vec())?;
+
+    Ok((camera_matrix, dist_coeffs))
+}
+
 // TODO: Add tests
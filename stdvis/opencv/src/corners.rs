@@ -0,0 +1,230 @@
+use anyhow::{bail, Context, Result};
+use opencv::{
+    core::{Point2f, Size, TermCriteria, TermCriteria_Type, Vector},
+    imgproc,
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use stdvis_core::{
+    geometry::{order_corners, Point},
+    model::TargetModel,
+    traits::ImageData,
+    types::{CameraConfig, Contour, ContourGroup, Image},
+};
+
+use crate::convert::AsMatView;
+
+/// Parameters for a `CornerRefiner`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CornerConfig {
+    /// Half the side length of the window searched around each corner, in
+    /// pixels.
+    #[serde(default = "CornerConfig::default_window")]
+    pub window: u32,
+    /// The most refinement iterations to run for each corner.
+    #[serde(default = "CornerConfig::default_max_iterations")]
+    pub max_iterations: u32,
+    /// The distance, in pixels, a corner must move by for refinement to
+    /// continue.
+    #[serde(default = "CornerConfig::default_epsilon")]
+    pub epsilon: f64,
+}
+
+impl CornerConfig {
+    fn default_window() -> u32 {
+        5
+    }
+
+    fn default_max_iterations() -> u32 {
+        30
+    }
+
+    fn default_epsilon() -> f64 {
+        0.01
+    }
+}
+
+impl Default for CornerConfig {
+    fn default() -> Self {
+        Self {
+            window: Self::default_window(),
+            max_iterations: Self::default_max_iterations(),
+            epsilon: Self::default_epsilon(),
+        }
+    }
+}
+
+/// Finds the polygon corners of contours, refines them to sub-pixel accuracy
+/// on the source image and puts them in a canonical order: clockwise from the
+/// top-left.
+pub struct CornerRefiner {
+    config: CornerConfig,
+}
+
+impl CornerRefiner {
+    pub fn new(config: CornerConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &CornerConfig {
+        &self.config
+    }
+
+    /// Finds, refines and orders `count` corners of a contour.
+    pub fn contour_corners<I: ImageData>(
+        &self,
+        image: &Image<I>,
+        contour: &Contour,
+        count: usize,
+    ) -> Result<Vec<Point>> {
+        let gray = grayscale(image)?;
+
+        self.corners_on(&gray, image.camera, contour, count)
+    }
+
+    /// Replaces each contour in `group` with its refined and ordered corners,
+    /// matching contours to the pieces of `model` from left to right.
+    pub fn refine_group<'src, I: ImageData>(
+        &self,
+        image: &Image<'src, I>,
+        group: &mut ContourGroup<'src>,
+        model: &TargetModel,
+    ) -> Result<()> {
+        if group.contours.len() != model.pieces.len() {
+            bail!(
+                "expected {} contours in group {}, found {}",
+                model.pieces.len(),
+                group.id,
+                group.contours.len()
+            );
+        }
+
+        let gray = grayscale(image)?;
+
+        group.contours.sort_by(|a, b| {
+            let (a, b) = (
+                a.centroid().unwrap_or_default(),
+                b.centroid().unwrap_or_default(),
+            );
+            a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        for (contour, piece) in group.contours.iter_mut().zip(&model.pieces) {
+            let corners = self.corners_on(&gray, group.camera, contour, piece.corners.len())?;
+            *contour = Contour::new(corners);
+        }
+
+        Ok(())
+    }
+
+    /// Refines points to sub-pixel accuracy on a grayscale image.
+    pub fn refine_points(&self, gray: &Mat, points: &[Point]) -> Result<Vec<Point>> {
+        let mut corners = points
+            .iter()
+            .map(|&(x, y)| Point2f::new(x, y))
+            .collect::<Vector<_>>();
+
+        let window = self.config.window as i32;
+
+        imgproc::corner_sub_pix(
+            gray,
+            &mut corners,
+            Size::new(window, window),
+            Size::new(-1, -1),
+            TermCriteria::new(
+                TermCriteria_Type::COUNT as i32 + TermCriteria_Type::EPS as i32,
+                self.config.max_iterations as i32,
+                self.config.epsilon,
+            )?,
+        )
+        .context("refining corners")?;
+
+        Ok(corners.iter().map(|point| (point.x, point.y)).collect())
+    }
+
+    fn corners_on(
+        &self,
+        gray: &Mat,
+        camera: &CameraConfig,
+        contour: &Contour,
+        count: usize,
+    ) -> Result<Vec<Point>> {
+        let corners = match contour.corners(count) {
+            Some(corners) => corners,
+            None => bail!("failed to find {count} corners on contour"),
+        };
+
+        let refined = self.refine_points(gray, &corners)?;
+
+        // A camera rolled clockwise sees the target rotated counter-clockwise.
+        Ok(order_corners(refined, -camera.pose.roll as f32))
+    }
+}
+
+/// Returns a single-channel copy of an image.
+pub(crate) fn grayscale<I: ImageData>(image: &Image<I>) -> Result<Mat> {
+    let mat = image.as_mat_view();
+
+    if mat.channels() == 1 {
+        return Ok(mat.clone());
+    }
+
+    let mut gray = Mat::default();
+    imgproc::cvt_color(&*mat, &mut gray, imgproc::COLOR_BGR2GRAY, 0)
+        .context("converting image to grayscale")?;
+
+    Ok(gray)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use opencv::core::{self, Rect, Scalar, CV_8UC1};
+
+    use super::*;
+    use crate::camera::MatImageData;
+
+    #[test]
+    fn test_refine_square() {
+        let mut frame =
+            Mat::new_rows_cols_with_default(100, 100, CV_8UC1, Scalar::all(0.)).unwrap();
+        imgproc::rectangle(
+            &mut frame,
+            Rect::new(20, 20, 40, 40),
+            Scalar::all(255.),
+            imgproc::FILLED,
+            imgproc::LINE_8,
+            0,
+        )
+        .unwrap();
+
+        let mut blurred = Mat::default();
+        imgproc::gaussian_blur(
+            &frame,
+            &mut blurred,
+            Size::new(5, 5),
+            0.,
+            0.,
+            core::BORDER_DEFAULT,
+        )
+        .unwrap();
+
+        let config = CameraConfig::default();
+        let image = Image::new(Instant::now(), 0, &config, MatImageData::new(blurred));
+
+        // Rough corners, starting from the bottom-right.
+        let contour = Contour::new(vec![(58., 61.), (18., 58.), (21., 21.), (61., 19.)]);
+
+        let refiner = CornerRefiner::new(CornerConfig::default());
+        let corners = refiner.contour_corners(&image, &contour, 4).unwrap();
+
+        let expected = [(19.5, 19.5), (59.5, 19.5), (59.5, 59.5), (19.5, 59.5)];
+        for ((x, y), (ex, ey)) in corners.into_iter().zip(expected) {
+            assert!(
+                (x - ex).abs() < 1. && (y - ey).abs() < 1.,
+                "corner ({x}, {y}) is not near ({ex}, {ey})"
+            );
+        }
+    }
+}
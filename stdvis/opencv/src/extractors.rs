@@ -0,0 +1,15 @@
+#[cfg(feature = "apriltag")]
+mod apriltag;
+mod aruco;
+mod hsv;
+
+#[cfg(feature = "apriltag")]
+pub use self::apriltag::{AprilTagConfig, AprilTagExtractor, TagFamily};
+pub use self::aruco::{
+    ArucoConfig, ArucoDictionary, ArucoExtractor, CharucoConfig, CharucoExtractor,
+    CornerRefinement, Markers,
+};
+
+pub use self::hsv::{
+    HsvThresholdConfig, HsvThresholdExtractor, KernelShape, Morphology, MorphologyOp,
+};
@@ -0,0 +1,247 @@
+use std::sync::Mutex;
+
+use ::apriltag::{Detector, DetectorBuilder, Family};
+use anyhow::{Context, Result};
+use opencv::prelude::*;
+use serde::{Deserialize, Serialize};
+use stdvis_core::{
+    traits::{ContourExtractor, ImageData},
+    types::{Contour, ContourGroup, Fiducial, Image},
+};
+
+use crate::corners::grayscale;
+
+/// A family of AprilTag markers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TagFamily {
+    #[default]
+    Tag36h11,
+    Tag16h5,
+}
+
+impl TagFamily {
+    /// The family's name, as used by the AprilTag library.
+    pub fn name(self) -> &'static str {
+        match self {
+            TagFamily::Tag36h11 => "tag36h11",
+            TagFamily::Tag16h5 => "tag16h5",
+        }
+    }
+
+    fn family(self) -> Family {
+        match self {
+            TagFamily::Tag36h11 => Family::tag_36h11(),
+            TagFamily::Tag16h5 => Family::tag_16h5(),
+        }
+    }
+}
+
+/// Parameters for an `AprilTagExtractor`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AprilTagConfig {
+    #[serde(default)]
+    pub family: TagFamily,
+    /// The most bit errors to correct when decoding a tag.
+    #[serde(default = "AprilTagConfig::default_max_hamming")]
+    pub max_hamming: u8,
+    /// The smallest decision margin for a tag to be kept. Small tags from
+    /// families with few bits, such as 16h5, produce many false detections
+    /// with low margins.
+    #[serde(default)]
+    pub min_decision_margin: f32,
+    /// The factor the image is downsampled by when finding quads. Corners
+    /// are still found at full resolution.
+    #[serde(default = "AprilTagConfig::default_decimation")]
+    pub decimation: f32,
+    /// The standard deviation of the Gaussian blur applied before finding
+    /// quads, in pixels.
+    #[serde(default)]
+    pub sigma: f32,
+    /// Whether to fit quad edges to the image gradient.
+    #[serde(default = "AprilTagConfig::default_refine_edges")]
+    pub refine_edges: bool,
+    #[serde(default = "AprilTagConfig::default_threads")]
+    pub threads: u8,
+}
+
+impl AprilTagConfig {
+    fn default_max_hamming() -> u8 {
+        1
+    }
+
+    fn default_decimation() -> f32 {
+        2.
+    }
+
+    fn default_refine_edges() -> bool {
+        true
+    }
+
+    fn default_threads() -> u8 {
+        1
+    }
+}
+
+impl Default for AprilTagConfig {
+    fn default() -> Self {
+        Self {
+            family: TagFamily::default(),
+            max_hamming: Self::default_max_hamming(),
+            min_decision_margin: 0.,
+            decimation: Self::default_decimation(),
+            sigma: 0.,
+            refine_edges: Self::default_refine_edges(),
+            threads: Self::default_threads(),
+        }
+    }
+}
+
+/// A `ContourExtractor` which detects AprilTag markers.
+///
+/// Each tag becomes a group whose id is the tag's id, with a single contour
+/// holding its corners clockwise from the top-left as printed, however the tag
+/// is rotated in the image.
+pub struct AprilTagExtractor {
+    config: AprilTagConfig,
+    detector: Mutex<Detector>,
+}
+
+impl AprilTagExtractor {
+    pub fn new(config: AprilTagConfig) -> Result<Self> {
+        let mut detector = DetectorBuilder::new()
+            .add_family_bits(config.family.family(), config.max_hamming as usize)
+            .build()
+            .context("creating AprilTag detector")?;
+
+        detector.set_decimation(config.decimation);
+        detector.set_sigma(config.sigma);
+        detector.set_refine_edges(config.refine_edges);
+        detector.set_thread_number(config.threads.max(1));
+
+        Ok(Self {
+            config,
+            detector: Mutex::new(detector),
+        })
+    }
+
+    pub fn config(&self) -> &AprilTagConfig {
+        &self.config
+    }
+}
+
+impl ContourExtractor for AprilTagExtractor {
+    fn extract_from<'src, I: ImageData>(
+        &'src self,
+        image: &Image<'src, I>,
+    ) -> Result<Vec<ContourGroup<'src>>> {
+        let gray = grayscale(image)?;
+        let (width, height) = (gray.cols() as usize, gray.rows() as usize);
+
+        let mut tag_image = ::apriltag::Image::zeros_with_stride(width, height, width)
+            .context("allocating AprilTag image")?;
+
+        // Rows are copied one at a time, since either image may pad its rows.
+        let stride = tag_image.stride();
+        let buffer = tag_image.as_slice_mut();
+        for y in 0..height {
+            let row = gray
+                .at_row::<u8>(y as i32)
+                .context("reading grayscale row")?;
+            buffer[y * stride..][..width].copy_from_slice(row);
+        }
+
+        let detections = self.detector.lock().unwrap().detect(&tag_image);
+
+        let mut groups = detections
+            .into_iter()
+            .filter(|detection| detection.decision_margin() >= self.config.min_decision_margin)
+            .map(|detection| {
+                // The library lists corners counter-clockwise from the
+                // bottom-left of the tag.
+                let corners = detection
+                    .corners()
+                    .iter()
+                    .rev()
+                    .map(|&[x, y]| (x as f32, y as f32))
+                    .collect();
+
+                ContourGroup {
+                    id: detection.id() as u16,
+                    camera: image.camera,
+                    contours: vec![Contour::new(corners)],
+                    fiducial: Some(Fiducial {
+                        family: self.config.family.name().to_owned(),
+                        hamming: Some(detection.hamming() as u8),
+                        decision_margin: Some(detection.decision_margin()),
+                    }),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        groups.sort_by_key(|group| group.id);
+
+        Ok(groups)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use opencv::{
+        aruco::{self, PREDEFINED_DICTIONARY_NAME},
+        core::{Rect, Scalar, CV_8UC1},
+    };
+    use stdvis_core::types::CameraConfig;
+
+    use super::*;
+    use crate::camera::MatImageData;
+
+    #[test]
+    fn test_detect_tag() {
+        // OpenCV's copy of the 36h11 family renders the same codes.
+        let dictionary =
+            aruco::get_predefined_dictionary(PREDEFINED_DICTIONARY_NAME::DICT_APRILTAG_36h11)
+                .unwrap();
+        let mut tag = Mat::default();
+        aruco::draw_marker(&dictionary, 12, 100, &mut tag, 1).unwrap();
+
+        let mut frame =
+            Mat::new_rows_cols_with_default(200, 200, CV_8UC1, Scalar::all(255.)).unwrap();
+        let mut roi = Mat::roi(&frame, Rect::new(50, 50, 100, 100)).unwrap();
+        tag.copy_to(&mut roi).unwrap();
+
+        let camera = CameraConfig::default();
+        let image = Image::new(Instant::now(), 0, &camera, MatImageData::new(frame));
+
+        let extractor = AprilTagExtractor::new(AprilTagConfig {
+            decimation: 1.,
+            ..AprilTagConfig::default()
+        })
+        .unwrap();
+        let groups = extractor.extract_from(&image).unwrap();
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].id, 12);
+
+        let fiducial = groups[0].fiducial.as_ref().unwrap();
+        assert_eq!(fiducial.family, "tag36h11");
+        assert_eq!(fiducial.hamming, Some(0));
+
+        // The corners are those of the tag's black border, clockwise.
+        let corners = &groups[0].contours[0].points;
+        assert_eq!(corners.len(), 4);
+        for &(x, y) in corners {
+            let near = |value: f32| (value - 49.5).abs() < 1.5 || (value - 149.5).abs() < 1.5;
+            assert!(near(x) && near(y), "corner ({x}, {y}) is not on the tag");
+        }
+
+        let twice_area = (0..4)
+            .map(|idx| {
+                let ((x0, y0), (x1, y1)) = (corners[idx], corners[(idx + 1) % 4]);
+                x0 * y1 - x1 * y0
+            })
+            .sum::<f32>();
+        assert!(twice_area > 0.);
+    }
+}
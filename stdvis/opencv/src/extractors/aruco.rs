@@ -0,0 +1,442 @@
+use anyhow::{bail, Context, Result};
+use opencv::{
+    aruco::{self, CharucoBoard, DetectorParameters, Dictionary, PREDEFINED_DICTIONARY_NAME},
+    core::{no_array, Point2f, Ptr, Vector},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use stdvis_core::{
+    traits::{ContourExtractor, ImageData},
+    types::{Contour, ContourGroup, Fiducial, Image},
+};
+
+use crate::{
+    convert::AsMatView,
+    corners::{grayscale, CornerConfig},
+};
+
+/// One of OpenCV's predefined marker dictionaries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArucoDictionary {
+    /// Markers of `size` by `size` bits, where `size` is 4 to 7, and `count`
+    /// is 50, 100, 250 or 1000.
+    Grid {
+        size: u8,
+        count: u16,
+    },
+    /// The dictionary from the original ArUco library.
+    Original,
+    AprilTag16h5,
+    AprilTag25h9,
+    AprilTag36h10,
+    AprilTag36h11,
+}
+
+impl ArucoDictionary {
+    /// The dictionary's name, as recorded in each marker's `Fiducial`.
+    pub fn name(self) -> String {
+        match self {
+            ArucoDictionary::Grid { size, count } => format!("aruco_{size}x{size}_{count}"),
+            ArucoDictionary::Original => "aruco_original".to_owned(),
+            ArucoDictionary::AprilTag16h5 => "tag16h5".to_owned(),
+            ArucoDictionary::AprilTag25h9 => "tag25h9".to_owned(),
+            ArucoDictionary::AprilTag36h10 => "tag36h10".to_owned(),
+            ArucoDictionary::AprilTag36h11 => "tag36h11".to_owned(),
+        }
+    }
+
+    fn cv_name(self) -> Result<PREDEFINED_DICTIONARY_NAME> {
+        use PREDEFINED_DICTIONARY_NAME::*;
+
+        Ok(match self {
+            ArucoDictionary::Grid { size, count } => match (size, count) {
+                (4, 50) => DICT_4X4_50,
+                (4, 100) => DICT_4X4_100,
+                (4, 250) => DICT_4X4_250,
+                (4, 1000) => DICT_4X4_1000,
+                (5, 50) => DICT_5X5_50,
+                (5, 100) => DICT_5X5_100,
+                (5, 250) => DICT_5X5_250,
+                (5, 1000) => DICT_5X5_1000,
+                (6, 50) => DICT_6X6_50,
+                (6, 100) => DICT_6X6_100,
+                (6, 250) => DICT_6X6_250,
+                (6, 1000) => DICT_6X6_1000,
+                (7, 50) => DICT_7X7_50,
+                (7, 100) => DICT_7X7_100,
+                (7, 250) => DICT_7X7_250,
+                (7, 1000) => DICT_7X7_1000,
+                _ => bail!("there is no predefined {size}x{size} dictionary of {count} markers"),
+            },
+            ArucoDictionary::Original => DICT_ARUCO_ORIGINAL,
+            ArucoDictionary::AprilTag16h5 => DICT_APRILTAG_16h5,
+            ArucoDictionary::AprilTag25h9 => DICT_APRILTAG_25h9,
+            ArucoDictionary::AprilTag36h10 => DICT_APRILTAG_36h10,
+            ArucoDictionary::AprilTag36h11 => DICT_APRILTAG_36h11,
+        })
+    }
+}
+
+/// The method used to refine the corners of each marker.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum CornerRefinement {
+    None,
+    /// Refine each corner on the image gradient.
+    #[default]
+    Subpix,
+    /// Fit lines to the marker's contour and intersect them.
+    Contour,
+    /// Use the AprilTag library's edge refinement.
+    AprilTag,
+}
+
+impl CornerRefinement {
+    fn cv_code(self) -> i32 {
+        match self {
+            CornerRefinement::None => aruco::CORNER_REFINE_NONE,
+            CornerRefinement::Subpix => aruco::CORNER_REFINE_SUBPIX,
+            CornerRefinement::Contour => aruco::CORNER_REFINE_CONTOUR,
+            CornerRefinement::AprilTag => aruco::CORNER_REFINE_APRILTAG,
+        }
+    }
+}
+
+/// Parameters for an `ArucoExtractor`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ArucoConfig {
+    pub dictionary: ArucoDictionary,
+    #[serde(default)]
+    pub refinement: CornerRefinement,
+    /// The search window and termination criteria for `Subpix` refinement.
+    #[serde(default)]
+    pub corners: CornerConfig,
+    /// The smallest marker perimeter, as a fraction of the image's larger
+    /// dimension.
+    #[serde(default = "ArucoConfig::default_min_perimeter")]
+    pub min_perimeter: f64,
+    /// The largest marker perimeter, as a fraction of the image's larger
+    /// dimension.
+    #[serde(default = "ArucoConfig::default_max_perimeter")]
+    pub max_perimeter: f64,
+    /// The fraction of the dictionary's correctable bits which may be
+    /// corrected when decoding a marker.
+    #[serde(default = "ArucoConfig::default_error_correction_rate")]
+    pub error_correction_rate: f64,
+}
+
+impl ArucoConfig {
+    fn default_min_perimeter() -> f64 {
+        0.03
+    }
+
+    fn default_max_perimeter() -> f64 {
+        4.
+    }
+
+    fn default_error_correction_rate() -> f64 {
+        0.6
+    }
+}
+
+/// A `ContourExtractor` which detects ArUco markers with OpenCV.
+///
+/// Each marker becomes a group whose id is the marker's id, with a single
+/// contour holding its corners clockwise from the top-left as printed.
+pub struct ArucoExtractor {
+    config: ArucoConfig,
+    dictionary: Ptr<Dictionary>,
+    parameters: Ptr<DetectorParameters>,
+}
+
+impl ArucoExtractor {
+    pub fn new(config: ArucoConfig) -> Result<Self> {
+        let dictionary = aruco::get_predefined_dictionary(config.dictionary.cv_name()?)
+            .context("loading ArUco dictionary")?;
+
+        let mut parameters =
+            DetectorParameters::create().context("creating ArUco detector parameters")?;
+        parameters.set_min_marker_perimeter_rate(config.min_perimeter);
+        parameters.set_max_marker_perimeter_rate(config.max_perimeter);
+        parameters.set_error_correction_rate(config.error_correction_rate);
+        parameters.set_corner_refinement_method(config.refinement.cv_code());
+        parameters.set_corner_refinement_win_size(config.corners.window as i32);
+        parameters.set_corner_refinement_max_iterations(config.corners.max_iterations as i32);
+        parameters.set_corner_refinement_min_accuracy(config.corners.epsilon);
+
+        Ok(Self {
+            config,
+            dictionary,
+            parameters,
+        })
+    }
+
+    pub fn config(&self) -> &ArucoConfig {
+        &self.config
+    }
+
+    /// Detects the markers in an image, returning their corners and ids.
+    pub fn detect<I: ImageData>(&self, image: &Image<I>) -> Result<Markers> {
+        let view = image.as_mat_view();
+        let gray;
+
+        // AprilTag refinement only accepts grayscale images.
+        let source = match self.config.refinement {
+            CornerRefinement::AprilTag => {
+                gray = grayscale(image)?;
+                &gray
+            }
+            _ => &*view,
+        };
+
+        let mut markers = Markers::default();
+
+        aruco::detect_markers(
+            source,
+            &self.dictionary,
+            &mut markers.corners,
+            &mut markers.ids,
+            &self.parameters,
+            &mut no_array(),
+            &no_array(),
+            &no_array(),
+        )
+        .context("detecting ArUco markers")?;
+
+        Ok(markers)
+    }
+}
+
+/// The markers detected in an image, in OpenCV's form.
+#[derive(Default)]
+pub struct Markers {
+    /// The corners of each marker, clockwise from the top-left as printed.
+    pub corners: Vector<Vector<Point2f>>,
+    pub ids: Vector<i32>,
+}
+
+impl ContourExtractor for ArucoExtractor {
+    fn extract_from<'src, I: ImageData>(
+        &'src self,
+        image: &Image<'src, I>,
+    ) -> Result<Vec<ContourGroup<'src>>> {
+        let Markers { corners, ids } = self.detect(image)?;
+
+        let family = self.config.dictionary.name();
+
+        let mut groups = ids
+            .iter()
+            .zip(corners.iter())
+            .map(|(id, corners)| ContourGroup {
+                id: id as u16,
+                camera: image.camera,
+                contours: vec![Contour::new(
+                    corners.iter().map(|point| (point.x, point.y)).collect(),
+                )],
+                fiducial: Some(Fiducial {
+                    family: family.clone(),
+                    hamming: None,
+                    decision_margin: None,
+                }),
+            })
+            .collect::<Vec<_>>();
+
+        groups.sort_by_key(|group| group.id);
+
+        Ok(groups)
+    }
+}
+
+/// Parameters for a `CharucoExtractor`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CharucoConfig {
+    /// The detector for the board's markers.
+    pub markers: ArucoConfig,
+    /// The number of chessboard squares along the board's width and height.
+    pub squares: (u32, u32),
+    /// The side length of each chessboard square.
+    pub square_length: f32,
+    /// The side length of each marker, in the same units as `square_length`.
+    pub marker_length: f32,
+    /// The fewest adjacent markers a chessboard corner must have to be
+    /// interpolated.
+    #[serde(default = "CharucoConfig::default_min_markers")]
+    pub min_markers: u32,
+}
+
+impl CharucoConfig {
+    fn default_min_markers() -> u32 {
+        2
+    }
+}
+
+/// A `ContourExtractor` which finds the chessboard corners of a ChArUco
+/// board, interpolated from the markers between them.
+///
+/// Each corner found becomes a group whose id is the corner's id on the board,
+/// counting along rows from the top-left, with a single one-point contour.
+pub struct CharucoExtractor {
+    config: CharucoConfig,
+    markers: ArucoExtractor,
+    board: Ptr<CharucoBoard>,
+}
+
+impl CharucoExtractor {
+    pub fn new(config: CharucoConfig) -> Result<Self> {
+        let markers = ArucoExtractor::new(config.markers.clone())?;
+
+        let board = CharucoBoard::create(
+            config.squares.0 as i32,
+            config.squares.1 as i32,
+            config.square_length,
+            config.marker_length,
+            &markers.dictionary,
+        )
+        .context("creating ChArUco board")?;
+
+        Ok(Self {
+            config,
+            markers,
+            board,
+        })
+    }
+
+    pub fn config(&self) -> &CharucoConfig {
+        &self.config
+    }
+
+    pub fn board(&self) -> &Ptr<CharucoBoard> {
+        &self.board
+    }
+}
+
+impl ContourExtractor for CharucoExtractor {
+    fn extract_from<'src, I: ImageData>(
+        &'src self,
+        image: &Image<'src, I>,
+    ) -> Result<Vec<ContourGroup<'src>>> {
+        let markers = self.markers.detect(image)?;
+
+        if markers.ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut corners = Vector::<Point2f>::new();
+        let mut ids = Vector::<i32>::new();
+
+        aruco::interpolate_corners_charuco(
+            &markers.corners,
+            &markers.ids,
+            &*image.as_mat_view(),
+            &self.board,
+            &mut corners,
+            &mut ids,
+            &no_array(),
+            &no_array(),
+            self.config.min_markers as i32,
+        )
+        .context("interpolating ChArUco corners")?;
+
+        let family = format!(
+            "charuco_{}x{}_{}",
+            self.config.squares.0,
+            self.config.squares.1,
+            self.config.markers.dictionary.name()
+        );
+
+        let mut groups = ids
+            .iter()
+            .zip(corners.iter())
+            .map(|(id, corner)| ContourGroup {
+                id: id as u16,
+                camera: image.camera,
+                contours: vec![Contour::new(vec![(corner.x, corner.y)])],
+                fiducial: Some(Fiducial {
+                    family: family.clone(),
+                    hamming: None,
+                    decision_margin: None,
+                }),
+            })
+            .collect::<Vec<_>>();
+
+        groups.sort_by_key(|group| group.id);
+
+        Ok(groups)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use opencv::core::{Rect, Scalar, Size, CV_8UC1};
+    use stdvis_core::types::CameraConfig;
+
+    use super::*;
+    use crate::camera::MatImageData;
+
+    fn config() -> ArucoConfig {
+        ArucoConfig {
+            dictionary: ArucoDictionary::Grid { size: 4, count: 50 },
+            refinement: CornerRefinement::Subpix,
+            corners: CornerConfig::default(),
+            min_perimeter: ArucoConfig::default_min_perimeter(),
+            max_perimeter: ArucoConfig::default_max_perimeter(),
+            error_correction_rate: ArucoConfig::default_error_correction_rate(),
+        }
+    }
+
+    #[test]
+    fn test_detect_marker() {
+        let extractor = ArucoExtractor::new(config()).unwrap();
+
+        let mut marker = Mat::default();
+        aruco::draw_marker(&extractor.dictionary, 7, 100, &mut marker, 1).unwrap();
+
+        let mut frame =
+            Mat::new_rows_cols_with_default(200, 200, CV_8UC1, Scalar::all(255.)).unwrap();
+        let mut roi = Mat::roi(&frame, Rect::new(50, 50, 100, 100)).unwrap();
+        marker.copy_to(&mut roi).unwrap();
+
+        let camera = CameraConfig::default();
+        let image = Image::new(Instant::now(), 0, &camera, MatImageData::new(frame));
+
+        let groups = extractor.extract_from(&image).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].id, 7);
+
+        let expected = [(49.5, 49.5), (149.5, 49.5), (149.5, 149.5), (49.5, 149.5)];
+        for (&(x, y), (ex, ey)) in groups[0].contours[0].points.iter().zip(expected) {
+            assert!(
+                (x - ex).abs() < 1.5 && (y - ey).abs() < 1.5,
+                "corner ({x}, {y}) is not near ({ex}, {ey})"
+            );
+        }
+    }
+
+    #[test]
+    fn test_charuco_corners() {
+        let extractor = CharucoExtractor::new(CharucoConfig {
+            markers: config(),
+            squares: (5, 4),
+            square_length: 0.04,
+            marker_length: 0.03,
+            min_markers: CharucoConfig::default_min_markers(),
+        })
+        .unwrap();
+
+        let mut frame = Mat::default();
+        extractor
+            .board
+            .clone()
+            .draw(Size::new(300, 240), &mut frame, 20, 1)
+            .unwrap();
+
+        let camera = CameraConfig::default();
+        let image = Image::new(Instant::now(), 0, &camera, MatImageData::new(frame));
+
+        // Every inner corner of the 5 by 4 board is found.
+        let groups = extractor.extract_from(&image).unwrap();
+        let ids = groups.iter().map(|group| group.id).collect::<Vec<_>>();
+        assert_eq!(ids, (0..12).collect::<Vec<_>>());
+    }
+}
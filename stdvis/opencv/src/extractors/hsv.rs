@@ -0,0 +1,273 @@
+use anyhow::Result;
+use opencv::{
+    core::{self, Point, Scalar, Size},
+    imgproc,
+    prelude::*,
+    types::VectorOfVectorOfPoint,
+};
+use serde::{Deserialize, Serialize};
+use stdvis_core::{
+    traits::{ContourExtractor, ImageData},
+    types::{Contour, ContourGroup, Image},
+};
+
+use crate::convert::AsMatView;
+
+/// The largest hue value in OpenCV's 8-bit HSV representation.
+const MAX_HUE: u8 = 180;
+
+/// A morphological operation applied to the threshold mask.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum MorphologyOp {
+    Erode,
+    Dilate,
+    Open,
+    Close,
+}
+
+impl MorphologyOp {
+    fn cv_code(self) -> i32 {
+        match self {
+            MorphologyOp::Erode => imgproc::MORPH_ERODE,
+            MorphologyOp::Dilate => imgproc::MORPH_DILATE,
+            MorphologyOp::Open => imgproc::MORPH_OPEN,
+            MorphologyOp::Close => imgproc::MORPH_CLOSE,
+        }
+    }
+}
+
+/// The shape of a morphology kernel.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum KernelShape {
+    #[default]
+    Rect,
+    Ellipse,
+    Cross,
+}
+
+impl KernelShape {
+    fn cv_code(self) -> i32 {
+        match self {
+            KernelShape::Rect => imgproc::MORPH_RECT,
+            KernelShape::Ellipse => imgproc::MORPH_ELLIPSE,
+            KernelShape::Cross => imgproc::MORPH_CROSS,
+        }
+    }
+}
+
+/// A single morphology step, applied to the mask after thresholding.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Morphology {
+    pub op: MorphologyOp,
+    #[serde(default)]
+    pub shape: KernelShape,
+    /// The side length of the kernel in pixels.
+    pub size: u32,
+    #[serde(default = "Morphology::default_iterations")]
+    pub iterations: u32,
+}
+
+impl Morphology {
+    fn default_iterations() -> u32 {
+        1
+    }
+}
+
+/// Parameters for an `HsvThresholdExtractor`.
+///
+/// Hue uses OpenCV's `0..=180` scale. If the lower hue is greater than the
+/// upper hue, the range wraps around through zero, which is useful for red.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HsvThresholdConfig {
+    pub lower: (u8, u8, u8),
+    pub upper: (u8, u8, u8),
+    /// Morphology steps to apply to the mask, in order.
+    #[serde(default)]
+    pub morphology: Vec<Morphology>,
+}
+
+/// A `ContourExtractor` which thresholds a BGR image in HSV space and finds
+/// the external contours of the resulting mask.
+///
+/// All contours found in an image are returned in a single group.
+pub struct HsvThresholdExtractor {
+    config: HsvThresholdConfig,
+    kernels: Vec<Mat>,
+}
+
+impl HsvThresholdExtractor {
+    pub fn new(config: HsvThresholdConfig) -> Result<Self> {
+        let kernels = config
+            .morphology
+            .iter()
+            .map(|step| {
+                let size = step.size as i32;
+                imgproc::get_structuring_element(
+                    step.shape.cv_code(),
+                    Size::new(size, size),
+                    Point::new(-1, -1),
+                )
+            })
+            .collect::<opencv::Result<_>>()?;
+
+        Ok(Self { config, kernels })
+    }
+
+    pub fn config(&self) -> &HsvThresholdConfig {
+        &self.config
+    }
+
+    /// Computes the binary mask of pixels within the configured HSV range.
+    pub fn threshold(&self, image: &Mat) -> Result<Mat> {
+        let mut hsv = Mat::default();
+        imgproc::cvt_color(image, &mut hsv, imgproc::COLOR_BGR2HSV, 0)?;
+
+        let HsvThresholdConfig { lower, upper, .. } = self.config;
+        let mut mask = Mat::default();
+
+        if lower.0 <= upper.0 {
+            core::in_range(&hsv, &hsv_scalar(lower), &hsv_scalar(upper), &mut mask)?;
+        } else {
+            let mut low_mask = Mat::default();
+            let mut high_mask = Mat::default();
+
+            core::in_range(
+                &hsv,
+                &hsv_scalar((0, lower.1, lower.2)),
+                &hsv_scalar(upper),
+                &mut low_mask,
+            )?;
+            core::in_range(
+                &hsv,
+                &hsv_scalar(lower),
+                &hsv_scalar((MAX_HUE, upper.1, upper.2)),
+                &mut high_mask,
+            )?;
+            core::bitwise_or(&low_mask, &high_mask, &mut mask, &core::no_array())?;
+        }
+
+        for (step, kernel) in self.config.morphology.iter().zip(&self.kernels) {
+            let mut out = Mat::default();
+
+            imgproc::morphology_ex(
+                &mask,
+                &mut out,
+                step.op.cv_code(),
+                kernel,
+                Point::new(-1, -1),
+                step.iterations as i32,
+                core::BORDER_CONSTANT,
+                imgproc::morphology_default_border_value()?,
+            )?;
+
+            mask = out;
+        }
+
+        Ok(mask)
+    }
+}
+
+impl ContourExtractor for HsvThresholdExtractor {
+    fn extract_from<'src, I: ImageData>(
+        &'src self,
+        image: &Image<'src, I>,
+    ) -> Result<Vec<ContourGroup<'src>>> {
+        let mask = self.threshold(&image.as_mat_view())?;
+
+        let mut cv_contours = VectorOfVectorOfPoint::new();
+        imgproc::find_contours(
+            &mask,
+            &mut cv_contours,
+            imgproc::RETR_EXTERNAL,
+            imgproc::CHAIN_APPROX_SIMPLE,
+            Point::default(),
+        )?;
+
+        let contours = cv_contours
+            .iter()
+            .map(|contour| Contour {
+                points: contour
+                    .iter()
+                    .map(|point| (point.x as f32, point.y as f32))
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+
+        if contours.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Ok(vec![ContourGroup {
+            id: 0,
+            camera: image.camera,
+            contours,
+            fiducial: None,
+        }])
+    }
+}
+
+fn hsv_scalar((h, s, v): (u8, u8, u8)) -> Scalar {
+    Scalar::new(h as f64, s as f64, v as f64, 0.)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use opencv::core::{Rect, CV_8UC3};
+    use stdvis_core::types::CameraConfig;
+
+    use super::*;
+    use crate::camera::MatImageData;
+
+    #[test]
+    fn test_threshold_blobs() {
+        let mut frame = Mat::new_rows_cols_with_default(80, 100, CV_8UC3, Scalar::all(0.)).unwrap();
+
+        // A green square and a red one, in BGR.
+        for (rect, color) in [
+            (Rect::new(20, 30, 30, 30), Scalar::new(0., 255., 0., 0.)),
+            (Rect::new(70, 10, 20, 10), Scalar::new(0., 0., 255., 0.)),
+        ] {
+            imgproc::rectangle(&mut frame, rect, color, imgproc::FILLED, imgproc::LINE_8, 0)
+                .unwrap();
+        }
+
+        let config = CameraConfig::default();
+        let image = Image::new(Instant::now(), 0, &config, MatImageData::new(frame));
+
+        let green = HsvThresholdExtractor::new(HsvThresholdConfig {
+            lower: (50, 100, 100),
+            upper: (70, 255, 255),
+            morphology: Vec::new(),
+        })
+        .unwrap();
+
+        let groups = green.extract_from(&image).unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].contours.len(), 1);
+
+        let contour = &groups[0].contours[0];
+        assert_eq!(contour.points.len(), 4);
+        let bounds = contour.bounding_box();
+        assert_eq!((bounds.x, bounds.y), (20., 30.));
+        assert_eq!((bounds.width, bounds.height), (29., 29.));
+
+        // A hue range which wraps through zero finds only the red blob.
+        let red = HsvThresholdExtractor::new(HsvThresholdConfig {
+            lower: (170, 100, 100),
+            upper: (10, 255, 255),
+            morphology: vec![Morphology {
+                op: MorphologyOp::Open,
+                shape: KernelShape::Rect,
+                size: 3,
+                iterations: 1,
+            }],
+        })
+        .unwrap();
+
+        let groups = red.extract_from(&image).unwrap();
+        assert_eq!(groups[0].contours.len(), 1);
+        assert_eq!(groups[0].contours[0].bounding_box().center(), (79.5, 14.5));
+    }
+}
@@ -1,2 +1,9 @@
+pub mod analyzers;
 pub mod camera;
 pub mod convert;
+pub mod corners;
+pub mod extractors;
+pub mod localization;
+pub mod overlay;
+pub mod preprocess;
+pub mod stages;
@@ -0,0 +1,393 @@
+use anyhow::{bail, ensure, Context, Result};
+use opencv::{
+    calib3d,
+    core::{Point2f, Point3f, Vector},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use stdvis_core::{
+    field::FieldMap, fusion::PoseMeasurement, model::TargetModel, odometry::FieldPose,
+    transform::Transform, types::ContourGroup,
+};
+
+use crate::{
+    analyzers::{solution_with_errors, PnpMethod},
+    convert::intrinsics_to_mats,
+};
+
+/// The smallest noise taken for each observed corner, in pixels, so that a
+/// perfect fit isn't treated as certain.
+const MIN_CORNER_NOISE: f64 = 0.5;
+
+/// Parameters for a `FieldLocalizer`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LocalizerConfig {
+    #[serde(default)]
+    pub method: PnpMethod,
+    /// The largest RMS reprojection error, in pixels, over every corner for a
+    /// solution to be accepted.
+    #[serde(default = "LocalizerConfig::default_max_reprojection_error")]
+    pub max_reprojection_error: f64,
+}
+
+impl LocalizerConfig {
+    fn default_max_reprojection_error() -> f64 {
+        4.
+    }
+}
+
+impl Default for LocalizerConfig {
+    fn default() -> Self {
+        Self {
+            method: PnpMethod::default(),
+            max_reprojection_error: Self::default_max_reprojection_error(),
+        }
+    }
+}
+
+/// The RMS reprojection error of one landmark's corners, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LandmarkResidual {
+    pub id: u16,
+    pub reprojection_error: f64,
+}
+
+/// The pose of the robot on the field, found from the landmarks in a frame.
+#[derive(Clone, Debug)]
+pub struct Localization {
+    pub robot: FieldPose,
+    /// The transform from the robot frame into the field frame.
+    pub robot_to_field: Transform,
+    /// The transform from the camera frame into the field frame.
+    pub camera_to_field: Transform,
+    /// The RMS reprojection error over every corner, in pixels.
+    pub reprojection_error: f64,
+    /// The reprojection error of each landmark used, in the order given.
+    pub residuals: Vec<LandmarkResidual>,
+    /// The id of the camera which saw the landmarks.
+    pub camera: u8,
+    /// The standard deviation of the robot's position, in meters.
+    pub position_std_dev: f64,
+    /// The standard deviation of the robot's heading, in radians.
+    pub heading_std_dev: f64,
+}
+
+impl Localization {
+    /// Returns this localization as a measurement for
+    /// [`Fuser::fuse_poses`](stdvis_core::fusion::Fuser::fuse_poses).
+    pub fn measurement(&self, capture_time: u64) -> PoseMeasurement {
+        PoseMeasurement {
+            camera: self.camera,
+            pose: self.robot,
+            position_std_dev: self.position_std_dev,
+            heading_std_dev: self.heading_std_dev,
+            capture_time,
+        }
+    }
+}
+
+/// Finds the robot's pose on the field by solving for the camera's pose
+/// against every known landmark seen in a frame at once.
+///
+/// Groups must hold the ordered corners of each landmark's model pieces, as
+/// produced by the fiducial extractors or a `CornerRefiner`. Groups whose ids
+/// are not in the field map, or whose corners don't match the model, are
+/// ignored.
+pub struct FieldLocalizer {
+    config: LocalizerConfig,
+    field: FieldMap,
+    model: TargetModel,
+}
+
+impl FieldLocalizer {
+    pub fn new(field: FieldMap, config: LocalizerConfig) -> Result<Self> {
+        // Landmarks placed around a field don't share a plane, even when the
+        // model itself is planar.
+        ensure!(
+            !matches!(config.method, PnpMethod::Ippe),
+            "IPPE requires coplanar points and can't be used for localization"
+        );
+
+        let model = field.model.resolve()?;
+
+        Ok(Self {
+            config,
+            field,
+            model,
+        })
+    }
+
+    pub fn config(&self) -> &LocalizerConfig {
+        &self.config
+    }
+
+    pub fn field(&self) -> &FieldMap {
+        &self.field
+    }
+
+    /// Solves for the robot's pose from the groups found in one frame.
+    pub fn localize(&self, groups: &[ContourGroup]) -> Result<Localization> {
+        let camera = match groups.first() {
+            Some(group) => group.camera,
+            None => bail!("no landmarks to localize from"),
+        };
+
+        ensure!(
+            groups
+                .iter()
+                .all(|group| std::ptr::eq(group.camera, camera)),
+            "landmarks for localization must all be seen by one camera"
+        );
+
+        let mut ids = Vec::new();
+        let mut object_points = Vector::<Point3f>::new();
+        let mut image_points = Vector::<Point2f>::new();
+
+        for group in groups {
+            let landmark = match self.field.landmark(group.id) {
+                Some(landmark) => landmark,
+                None => continue,
+            };
+
+            let matches_model = group.contours.len() == self.model.pieces.len()
+                && group
+                    .contours
+                    .iter()
+                    .zip(&self.model.pieces)
+                    .all(|(contour, piece)| contour.points.len() == piece.corners.len());
+
+            if !matches_model {
+                continue;
+            }
+
+            let to_field = landmark.model_to_field();
+            for point in self.model.points() {
+                let [x, y, z] = to_field.apply(point);
+                object_points.push(Point3f::new(x as f32, y as f32, z as f32));
+            }
+
+            for contour in &group.contours {
+                for &(x, y) in &contour.points {
+                    image_points.push(Point2f::new(x, y));
+                }
+            }
+
+            ids.push(group.id);
+        }
+
+        ensure!(
+            object_points.len() >= 4,
+            "too few known landmark corners to localize from"
+        );
+
+        let (camera_matrix, dist_coeffs) =
+            intrinsics_to_mats(camera).context("converting camera intrinsics")?;
+
+        let mut rvec = Mat::default();
+        let mut tvec = Mat::default();
+
+        let solved = calib3d::solve_pnp(
+            &object_points,
+            &image_points,
+            &camera_matrix,
+            &dist_coeffs,
+            &mut rvec,
+            &mut tvec,
+            false,
+            self.config.method.cv_flag(),
+        )
+        .context("solving field PnP")?;
+
+        if !solved {
+            bail!("no field PnP solution found");
+        }
+
+        let (solution, squared_errors) = solution_with_errors(
+            &object_points,
+            &image_points,
+            &camera_matrix,
+            &dist_coeffs,
+            &rvec,
+            &tvec,
+        )?;
+
+        ensure!(
+            solution.reprojection_error <= self.config.max_reprojection_error,
+            "reprojection error of {} px exceeds limit for localization",
+            solution.reprojection_error
+        );
+
+        let count = self.model.point_count();
+        let residuals = ids
+            .into_iter()
+            .zip(squared_errors.chunks(count))
+            .map(|(id, errors)| LandmarkResidual {
+                id,
+                reprojection_error: (errors.iter().sum::<f64>() / count as f64).sqrt(),
+            })
+            .collect();
+
+        // Each corner's error moves the solution by about its angle at the
+        // camera, averaged over every corner, which at the landmarks' range
+        // becomes a lateral error in position.
+        let corner_noise = solution
+            .reprojection_error
+            .max(camera.reprojection_error)
+            .max(MIN_CORNER_NOISE);
+        let angle_std_dev =
+            corner_noise / camera.intrinsic_matrix[[0, 0]] / (object_points.len() as f64).sqrt();
+        let range = object_points
+            .iter()
+            .map(|point| {
+                let [x, y, z] =
+                    solution
+                        .pose
+                        .apply([point.x as f64, point.y as f64, point.z as f64]);
+                (x * x + y * y + z * z).sqrt()
+            })
+            .sum::<f64>()
+            / object_points.len() as f64;
+
+        // The solution maps field points into the camera frame.
+        let camera_to_field = solution.pose.inverse();
+        let robot_to_field = camera_to_field.then(&camera.pose.transform().inverse());
+
+        let [x, y, _] = robot_to_field.translation;
+        let (heading, _, _) = robot_to_field.euler();
+
+        Ok(Localization {
+            robot: FieldPose::new(x, y, heading),
+            robot_to_field,
+            camera_to_field,
+            reprojection_error: solution.reprojection_error,
+            residuals,
+            camera: camera.id,
+            position_std_dev: angle_std_dev * range,
+            heading_std_dev: angle_std_dev,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{arr1, arr2};
+    use stdvis_core::{
+        field::Landmark,
+        model::ModelRef,
+        types::{CameraConfig, Contour, Pose},
+    };
+
+    use super::*;
+
+    fn field() -> FieldMap {
+        let landmark = |id, y| Landmark {
+            id,
+            position: [4., y, 1.],
+            yaw: std::f64::consts::PI,
+            pitch: 0.,
+            roll: 0.,
+        };
+
+        FieldMap {
+            name: "wall".to_owned(),
+            model: ModelRef::Name("apriltag_36h11_6in".to_owned()),
+            landmarks: vec![landmark(1, 0.6), landmark(2, -0.4)],
+        }
+    }
+
+    /// Projects each landmark's corners into a camera on a robot at `robot`.
+    fn observe<'src>(
+        field: &FieldMap,
+        camera: &'src CameraConfig,
+        robot: FieldPose,
+    ) -> Vec<ContourGroup<'src>> {
+        let model = field.model.resolve().unwrap();
+        let robot_to_field = Transform::from_euler(robot.heading, 0., 0., [robot.x, robot.y, 0.]);
+        let field_to_camera = robot_to_field.then(&camera.pose.transform()).inverse();
+
+        field
+            .landmarks
+            .iter()
+            .map(|landmark| {
+                let to_field = landmark.model_to_field();
+                let contours = model
+                    .pieces
+                    .iter()
+                    .map(|piece| {
+                        let points = piece
+                            .corners
+                            .iter()
+                            .map(|&corner| {
+                                let [x, y, z] = field_to_camera.apply(to_field.apply(corner));
+                                ((320. - 500. * y / x) as f32, (240. - 500. * z / x) as f32)
+                            })
+                            .collect();
+                        Contour::new(points)
+                    })
+                    .collect();
+
+                ContourGroup {
+                    id: landmark.id,
+                    camera,
+                    contours,
+                    fiducial: None,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_localize() {
+        let camera = CameraConfig {
+            resolution: (640, 480),
+            pose: Pose {
+                angle: 0.2,
+                dist: 0.3,
+                height: 0.5,
+                yaw: 0.1,
+                pitch: 0.15,
+                ..Pose::default()
+            },
+            intrinsic_matrix: arr2(&[[500., 0., 320.], [0., 500., 240.], [0., 0., 1.]]),
+            distortion_coeffs: arr1(&[0., 0., 0., 0., 0.]),
+            ..CameraConfig::default()
+        };
+
+        let localizer = FieldLocalizer::new(field(), LocalizerConfig::default()).unwrap();
+        let robot = FieldPose::new(1.2, 0.3, -0.2);
+        let mut groups = observe(localizer.field(), &camera, robot);
+
+        let localization = localizer.localize(&groups).unwrap();
+        assert!((localization.robot.x - robot.x).abs() < 1e-3);
+        assert!((localization.robot.y - robot.y).abs() < 1e-3);
+        assert!((localization.robot.heading - robot.heading).abs() < 1e-3);
+        assert!(localization.reprojection_error < 0.01);
+        assert!(localization.position_std_dev > 0. && localization.position_std_dev < 0.01);
+
+        let ids = localization
+            .residuals
+            .iter()
+            .map(|residual| residual.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [1, 2]);
+
+        // The landmarks' residuals together make up the overall error.
+        groups[1].contours[0].points[0].0 += 3.;
+
+        let localization = localizer.localize(&groups).unwrap();
+        let [first, second] = [0, 1].map(|idx| localization.residuals[idx].reprojection_error);
+        let overall = ((first * first + second * second) / 2.).sqrt();
+        assert!(localization.reprojection_error > 0.1);
+        assert!((overall - localization.reprojection_error).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_reject_ippe() {
+        let config = LocalizerConfig {
+            method: PnpMethod::Ippe,
+            ..LocalizerConfig::default()
+        };
+
+        assert!(FieldLocalizer::new(field(), config).is_err());
+    }
+}
@@ -0,0 +1,491 @@
+//! Debug overlays showing what a pipeline found in each frame.
+
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use opencv::{
+    calib3d,
+    core::{no_array, Point, Point2f, Point3f, Rect, Scalar, Vector},
+    imgcodecs, imgproc,
+    prelude::*,
+    types::{VectorOfPoint, VectorOfVectorOfPoint},
+};
+use serde::{Deserialize, Serialize};
+use stdvis_core::{
+    geometry::order_corners,
+    pipeline::{Inspector, PipelineDefinition},
+    traits::ImageData,
+    transform::Transform,
+    types::{CameraConfig, ContourGroup, Image, VisionTarget},
+};
+
+use crate::{
+    analyzers::{pose_to_vecs, PnpAnalyzer},
+    camera::MatImageData,
+    convert::intrinsics_to_mats,
+};
+
+const CONTOUR_COLOR: (f64, f64, f64) = (0., 255., 0.);
+const BOUNDING_BOX_COLOR: (f64, f64, f64) = (255., 128., 0.);
+const CORNER_COLOR: (f64, f64, f64) = (0., 255., 255.);
+const REPROJECTION_COLOR: (f64, f64, f64) = (255., 0., 255.);
+const CROSSHAIR_COLOR: (f64, f64, f64) = (0., 0., 255.);
+const TEXT_COLOR: (f64, f64, f64) = (255., 255., 255.);
+
+/// The layers drawn by an `Annotator`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OverlayConfig {
+    /// The outline of every contour.
+    #[serde(default = "OverlayConfig::default_enabled")]
+    pub contours: bool,
+    /// The id of each group, above its bounding box.
+    #[serde(default = "OverlayConfig::default_enabled")]
+    pub group_ids: bool,
+    /// The bounding box around each group's contours.
+    #[serde(default = "OverlayConfig::default_enabled")]
+    pub bounding_boxes: bool,
+    /// The corners of each group, numbered in the order they are matched to
+    /// the target's model.
+    #[serde(default = "OverlayConfig::default_enabled")]
+    pub corners: bool,
+    /// The model's points projected with the pose the pipeline solved, which
+    /// should lie on the corners. Only drawn when the annotator has a
+    /// `PnpAnalyzer`.
+    #[serde(default = "OverlayConfig::default_enabled")]
+    pub reprojection: bool,
+    /// Lines through the camera's principal point.
+    #[serde(default = "OverlayConfig::default_enabled")]
+    pub crosshair: bool,
+    /// The measurements of each target, below its group's bounding box.
+    #[serde(default = "OverlayConfig::default_enabled")]
+    pub readouts: bool,
+    /// The thickness of lines, in pixels.
+    #[serde(default = "OverlayConfig::default_thickness")]
+    pub thickness: i32,
+}
+
+impl OverlayConfig {
+    fn default_enabled() -> bool {
+        true
+    }
+
+    fn default_thickness() -> i32 {
+        1
+    }
+}
+
+impl Default for OverlayConfig {
+    fn default() -> Self {
+        Self {
+            contours: Self::default_enabled(),
+            group_ids: Self::default_enabled(),
+            bounding_boxes: Self::default_enabled(),
+            corners: Self::default_enabled(),
+            reprojection: Self::default_enabled(),
+            crosshair: Self::default_enabled(),
+            readouts: Self::default_enabled(),
+            thickness: Self::default_thickness(),
+        }
+    }
+}
+
+/// Draws the groups and targets found in a frame onto a copy of it.
+pub struct Annotator {
+    config: OverlayConfig,
+    analyzer: Option<PnpAnalyzer>,
+}
+
+impl Annotator {
+    pub fn new(config: OverlayConfig) -> Self {
+        Self {
+            config,
+            analyzer: None,
+        }
+    }
+
+    /// Creates an annotator which uses `analyzer` to order each group's
+    /// corners to match its model, and whose model is projected with each
+    /// target's pose. It should be configured like the pipeline's analyzer.
+    pub fn with_analyzer(config: OverlayConfig, analyzer: PnpAnalyzer) -> Self {
+        Self {
+            config,
+            analyzer: Some(analyzer),
+        }
+    }
+
+    /// Creates an annotator for a pipeline's definition, with an analyzer
+    /// configured like the pipeline's if it uses a `pnp` analyzer.
+    pub fn for_definition(config: OverlayConfig, definition: &PipelineDefinition) -> Result<Self> {
+        match definition.analyzer.kind.as_str() {
+            "pnp" => {
+                let analyzer = PnpAnalyzer::new(definition.analyzer.parse_params()?)
+                    .context("creating overlay analyzer")?;
+                Ok(Self::with_analyzer(config, analyzer))
+            }
+            _ => Ok(Self::new(config)),
+        }
+    }
+
+    pub fn config(&self) -> &OverlayConfig {
+        &self.config
+    }
+
+    /// Returns a color copy of `image` with the enabled layers drawn on it.
+    ///
+    /// Targets, and the poses solved for them, are matched to groups by id. A
+    /// group whose corners can't be found or which has no pose is drawn
+    /// without them.
+    pub fn annotate(
+        &self,
+        image: &Image<MatImageData>,
+        groups: &[ContourGroup],
+        targets: &[VisionTarget],
+        poses: &[Option<Transform>],
+    ) -> Result<Mat> {
+        let mut canvas = Mat::default();
+        if image.as_raw().channels() == 1 {
+            imgproc::cvt_color(image.as_raw(), &mut canvas, imgproc::COLOR_GRAY2BGR, 0)
+                .context("converting frame to color")?;
+        } else {
+            canvas = image.as_raw().try_clone()?;
+        }
+
+        if self.config.crosshair {
+            self.draw_crosshair(&mut canvas, image.camera)?;
+        }
+
+        for group in groups {
+            let idx = targets.iter().position(|target| target.id == group.id);
+            let target = idx.map(|idx| &targets[idx]);
+            let pose = idx.and_then(|idx| poses.get(idx)?.as_ref());
+            self.draw_group(&mut canvas, group, target, pose)?;
+        }
+
+        Ok(canvas)
+    }
+
+    fn draw_group(
+        &self,
+        canvas: &mut Mat,
+        group: &ContourGroup,
+        target: Option<&VisionTarget>,
+        pose: Option<&Transform>,
+    ) -> Result<()> {
+        let config = &self.config;
+        let bounds = group_bounds(group);
+
+        if config.contours {
+            let outlines = group
+                .contours
+                .iter()
+                .map(|contour| {
+                    contour
+                        .points
+                        .iter()
+                        .map(|&(x, y)| to_point(x, y))
+                        .collect::<VectorOfPoint>()
+                })
+                .collect::<VectorOfVectorOfPoint>();
+
+            imgproc::polylines(
+                canvas,
+                &outlines,
+                true,
+                color(CONTOUR_COLOR),
+                config.thickness,
+                imgproc::LINE_AA,
+                0,
+            )?;
+        }
+
+        if config.bounding_boxes {
+            imgproc::rectangle(
+                canvas,
+                bounds,
+                color(BOUNDING_BOX_COLOR),
+                config.thickness,
+                imgproc::LINE_8,
+                0,
+            )?;
+        }
+
+        if config.group_ids {
+            self.draw_text(
+                canvas,
+                &format!("#{}", group.id),
+                Point::new(bounds.x, bounds.y - 4),
+            )?;
+        }
+
+        if config.corners {
+            for (idx, &(x, y)) in self.corners(group).iter().enumerate() {
+                let corner = to_point(x, y);
+
+                imgproc::circle(
+                    canvas,
+                    corner,
+                    3,
+                    color(CORNER_COLOR),
+                    config.thickness,
+                    imgproc::LINE_AA,
+                    0,
+                )?;
+                self.draw_text(
+                    canvas,
+                    &idx.to_string(),
+                    Point::new(corner.x + 4, corner.y - 4),
+                )?;
+            }
+        }
+
+        if config.reprojection {
+            for point in self.reprojection(group, pose)? {
+                imgproc::draw_marker(
+                    canvas,
+                    to_point(point.x, point.y),
+                    color(REPROJECTION_COLOR),
+                    imgproc::MARKER_TILTED_CROSS,
+                    8,
+                    config.thickness,
+                    imgproc::LINE_AA,
+                )?;
+            }
+        }
+
+        if let Some(target) = target.filter(|_| config.readouts) {
+            self.draw_text(
+                canvas,
+                &readout(target),
+                Point::new(bounds.x, bounds.y + bounds.height + 14),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_crosshair(&self, canvas: &mut Mat, camera: &CameraConfig) -> Result<()> {
+        let (width, height) = (canvas.cols(), canvas.rows());
+
+        let (cx, cy) = match camera.intrinsic_matrix.shape() {
+            [3, 3] => (
+                camera.intrinsic_matrix[[0, 2]] as i32,
+                camera.intrinsic_matrix[[1, 2]] as i32,
+            ),
+            _ => (width / 2, height / 2),
+        };
+
+        for (start, end) in [
+            (Point::new(0, cy), Point::new(width, cy)),
+            (Point::new(cx, 0), Point::new(cx, height)),
+        ] {
+            imgproc::line(
+                canvas,
+                start,
+                end,
+                color(CROSSHAIR_COLOR),
+                self.config.thickness,
+                imgproc::LINE_8,
+                0,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_text(&self, canvas: &mut Mat, text: &str, origin: Point) -> Result<()> {
+        imgproc::put_text(
+            canvas,
+            text,
+            origin,
+            imgproc::FONT_HERSHEY_SIMPLEX,
+            0.4,
+            color(TEXT_COLOR),
+            1,
+            imgproc::LINE_AA,
+            false,
+        )?;
+
+        Ok(())
+    }
+
+    /// Finds the group's corners, in model order if there is an analyzer and
+    /// otherwise clockwise from the top-left of each quadrilateral contour.
+    fn corners(&self, group: &ContourGroup) -> Vec<(f32, f32)> {
+        if let Some(analyzer) = &self.analyzer {
+            return analyzer.image_corners(group).unwrap_or_default();
+        }
+
+        let rotation = -group.camera.pose.roll as f32;
+
+        group
+            .contours
+            .iter()
+            .filter_map(|contour| contour.corners(4))
+            .flat_map(|corners| order_corners(corners, rotation))
+            .collect()
+    }
+
+    /// Projects the model's points with the pose solved for the group.
+    fn reprojection(
+        &self,
+        group: &ContourGroup,
+        pose: Option<&Transform>,
+    ) -> Result<Vector<Point2f>> {
+        let mut projected = Vector::new();
+
+        let (analyzer, pose) = match (&self.analyzer, pose) {
+            (Some(analyzer), Some(pose)) => (analyzer, pose),
+            _ => return Ok(projected),
+        };
+
+        let (rvec, tvec) = pose_to_vecs(pose)?;
+
+        let object_points = analyzer
+            .model()
+            .points()
+            .map(|[x, y, z]| Point3f::new(x as f32, y as f32, z as f32))
+            .collect::<Vector<_>>();
+        let (camera_matrix, dist_coeffs) =
+            intrinsics_to_mats(group.camera).context("converting camera intrinsics")?;
+
+        calib3d::project_points(
+            &object_points,
+            &rvec,
+            &tvec,
+            &camera_matrix,
+            &dist_coeffs,
+            &mut projected,
+            &mut no_array(),
+            0.,
+        )
+        .context("projecting model points")?;
+
+        Ok(projected)
+    }
+}
+
+/// An `Inspector` which writes an annotated copy of every frame to a
+/// directory, named by the frame's sequence number.
+pub struct OverlayWriter {
+    annotator: Annotator,
+    dir: PathBuf,
+}
+
+impl OverlayWriter {
+    pub fn new(annotator: Annotator, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            annotator,
+            dir: dir.into(),
+        }
+    }
+}
+
+impl Inspector<MatImageData> for OverlayWriter {
+    fn inspect(
+        &mut self,
+        image: &Image<MatImageData>,
+        groups: &[ContourGroup],
+        targets: &[VisionTarget],
+        poses: &[Option<Transform>],
+    ) -> Result<()> {
+        let annotated = self.annotator.annotate(image, groups, targets, poses)?;
+        let path = self.dir.join(format!("frame-{:06}.png", image.sequence));
+
+        imgcodecs::imwrite(
+            path.to_str()
+                .context("debug image path is not valid UTF-8")?,
+            &annotated,
+            &Vector::new(),
+        )
+        .with_context(|| format!("writing debug image {path:?}"))?;
+
+        Ok(())
+    }
+}
+
+/// Formats a target's measurements, with angles in degrees.
+fn readout(target: &VisionTarget) -> String {
+    format!(
+        "d {:.2} h {:.2} th {:.1} b {:.1} c {:.2}",
+        target.dist,
+        target.height,
+        target.theta.to_degrees(),
+        target.beta.to_degrees(),
+        target.confidence
+    )
+}
+
+fn group_bounds(group: &ContourGroup) -> Rect {
+    let mut points = group.contours.iter().flat_map(|contour| &contour.points);
+
+    let first = match points.next() {
+        Some(&(x, y)) => to_point(x, y),
+        None => return Rect::default(),
+    };
+
+    let (min, max) = points.fold((first, first), |(min, max), &(x, y)| {
+        let point = to_point(x, y);
+        (
+            Point::new(min.x.min(point.x), min.y.min(point.y)),
+            Point::new(max.x.max(point.x), max.y.max(point.y)),
+        )
+    });
+
+    Rect::new(min.x, min.y, max.x - min.x, max.y - min.y)
+}
+
+fn to_point(x: f32, y: f32) -> Point {
+    Point::new(x.round() as i32, y.round() as i32)
+}
+
+fn color((b, g, r): (f64, f64, f64)) -> Scalar {
+    Scalar::new(b, g, r, 0.)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use opencv::core::{self, Vec3b};
+    use stdvis_core::types::Contour;
+
+    use super::*;
+
+    #[test]
+    fn test_annotate() {
+        let config = CameraConfig::default();
+        let frame =
+            Mat::new_rows_cols_with_default(48, 64, core::CV_8UC1, Scalar::all(0.)).unwrap();
+        let image = Image::new(Instant::now(), 0, &config, MatImageData::new(frame));
+
+        let group = ContourGroup {
+            id: 7,
+            camera: &config,
+            contours: vec![Contour::new(vec![
+                (20., 10.),
+                (40., 10.),
+                (40., 30.),
+                (20., 30.),
+            ])],
+            fiducial: None,
+        };
+        let target = VisionTarget {
+            id: 7,
+            dist: 1.5,
+            ..VisionTarget::default()
+        };
+
+        let annotator = Annotator::new(OverlayConfig {
+            crosshair: false,
+            ..OverlayConfig::default()
+        });
+        let annotated = annotator
+            .annotate(&image, &[group], &[target], &[None])
+            .unwrap();
+
+        // The frame itself is left untouched.
+        assert_eq!(core::count_non_zero(image.as_raw()).unwrap(), 0);
+        assert_eq!(annotated.channels(), 3);
+        assert_eq!(**annotated.at_2d::<Vec3b>(10, 30).unwrap(), [255, 128, 0]);
+    }
+}
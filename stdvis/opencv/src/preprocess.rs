@@ -0,0 +1,80 @@
+use anyhow::{ensure, Context, Result};
+use opencv::{
+    core::{self, Size},
+    imgproc,
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+use stdvis_core::{pipeline::Preprocessor, traits::ImageData, types::Image};
+
+use crate::camera::MatImageData;
+
+/// Parameters for a `GaussianBlur`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlurConfig {
+    /// The side length of the kernel, in pixels, which must be odd.
+    #[serde(default = "BlurConfig::default_size")]
+    pub size: u32,
+    /// The standard deviation of the kernel, in pixels. If zero, it is
+    /// derived from the kernel's size.
+    #[serde(default)]
+    pub sigma: f64,
+}
+
+impl BlurConfig {
+    fn default_size() -> u32 {
+        5
+    }
+}
+
+impl Default for BlurConfig {
+    fn default() -> Self {
+        Self {
+            size: Self::default_size(),
+            sigma: 0.,
+        }
+    }
+}
+
+/// A `Preprocessor` which blurs each image to suppress sensor noise before
+/// thresholding.
+pub struct GaussianBlur {
+    config: BlurConfig,
+}
+
+impl GaussianBlur {
+    pub fn new(config: BlurConfig) -> Result<Self> {
+        ensure!(
+            config.size % 2 == 1,
+            "blur kernel size must be odd, but is {}",
+            config.size
+        );
+
+        Ok(Self { config })
+    }
+
+    pub fn config(&self) -> &BlurConfig {
+        &self.config
+    }
+}
+
+impl Preprocessor<MatImageData> for GaussianBlur {
+    fn process(&self, image: &mut Image<MatImageData>) -> Result<()> {
+        let size = self.config.size as i32;
+        let mut blurred = Mat::default();
+
+        imgproc::gaussian_blur(
+            image.as_raw(),
+            &mut blurred,
+            Size::new(size, size),
+            self.config.sigma,
+            self.config.sigma,
+            core::BORDER_DEFAULT,
+        )
+        .context("blurring image")?;
+
+        *image.as_raw_mut() = blurred;
+
+        Ok(())
+    }
+}
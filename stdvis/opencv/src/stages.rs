@@ -0,0 +1,34 @@
+//! The OpenCV-backed stages available to pipeline definitions.
+
+use stdvis_core::pipeline::StageRegistry;
+
+#[cfg(feature = "apriltag")]
+use crate::extractors::AprilTagExtractor;
+use crate::{
+    analyzers::PnpAnalyzer,
+    camera::MatImageData,
+    extractors::{ArucoExtractor, CharucoExtractor, HsvThresholdExtractor},
+    preprocess::GaussianBlur,
+};
+
+/// Registers every stage provided by this crate.
+pub fn register_stages(registry: &mut StageRegistry<MatImageData>) {
+    registry.register_preprocessor("gaussian_blur", GaussianBlur::new);
+
+    registry.register_extractor("hsv_threshold", HsvThresholdExtractor::new);
+    registry.register_extractor("aruco", ArucoExtractor::new);
+    registry.register_extractor("charuco", CharucoExtractor::new);
+    #[cfg(feature = "apriltag")]
+    registry.register_extractor("apriltag", AprilTagExtractor::new);
+
+    registry.register_analyzer("pnp", PnpAnalyzer::new);
+}
+
+/// Returns a registry holding the stages of both this crate and
+/// `stdvis-core`.
+pub fn stage_registry() -> StageRegistry<MatImageData> {
+    let mut registry = StageRegistry::new();
+    register_stages(&mut registry);
+
+    registry
+}
@@ -2,15 +2,19 @@ use anyhow::Result;
 use clap::Subcommand;
 
 mod calibrate;
+mod run;
 mod sample;
 
-use self::{calibrate::Calibrate, sample::Sample};
+use self::{calibrate::Calibrate, run::Run, sample::Sample};
 
 #[derive(Subcommand)]
 pub enum Commands {
     /// Perform camera calibration given a set of input images with a predefined target
     Calibrate(Calibrate),
 
+    /// Run a vision pipeline defined in a file, printing the targets found in each frame
+    Run(Run),
+
     /// Perform configurable bulk-sampling of images
     Sample(Sample),
 }
@@ -19,6 +23,7 @@ impl Commands {
     pub fn execute(self) -> Result<()> {
         match self {
             Commands::Calibrate(calibrate) => calibrate.execute(),
+            Commands::Run(run) => run.execute(),
             Commands::Sample(sample) => sample.execute(),
         }
     }
@@ -14,6 +14,7 @@ use opencv::{
 
 use stdvis_core::types::CameraConfig;
 use stdvis_opencv::convert::AsArrayView;
+use tracing::{info, info_span, warn};
 
 const MIN_CALIBRATION_IMAGES: usize = 3;
 
@@ -84,7 +85,9 @@ impl Calibrate {
         let mut image_size = None;
         let pattern_size = Size::new(self.width() as i32, self.height() as i32);
 
-        for path in self.image_paths {
+        for (idx, path) in self.image_paths.into_iter().enumerate() {
+            let _span = info_span!("image", index = idx, count = num_images, ?path).entered();
+
             let image = imgcodecs::imread(path.to_str().unwrap(), IMREAD_COLOR)
                 .context("reading image from disk")?;
 
@@ -122,7 +125,7 @@ impl Calibrate {
             }
 
             if !found {
-                println!("Failed to find checkerboard corners for image: {path:?}");
+                warn!("failed to find checkerboard corners");
                 continue;
             }
 
@@ -137,18 +140,21 @@ impl Calibrate {
             )
             .context("estimating checkerboard sharpness")?;
 
-            println!(
-                "avg. sharpness: {}, avg. brightness (min, max): ({}, {}) for image: {path:?}",
-                sharpness_stats[0], sharpness_stats[1], sharpness_stats[2]
+            info!(
+                sharpness = sharpness_stats[0],
+                min_brightness = sharpness_stats[1],
+                max_brightness = sharpness_stats[2],
+                "found checkerboard corners"
             );
 
             object_points.push(template_obj_points.clone());
             image_points.push(corners);
         }
 
-        println!(
-            "Successfully performed corner-finding on {} images",
-            image_points.len()
+        info!(
+            found = image_points.len(),
+            count = num_images,
+            "finished corner-finding"
         );
 
         if image_points.len() < MIN_CALIBRATION_IMAGES {
@@ -187,7 +193,7 @@ impl Calibrate {
         )
         .context("calibrating camera")?;
 
-        println!("Calibration finished with reprojection error: {reproj_error}");
+        info!(reprojection_error = reproj_error, "finished calibration");
 
         let mut config_file = fs::OpenOptions::new()
             .read(true)
@@ -226,6 +232,8 @@ impl Calibrate {
             .context("converting distortion_coeffs Mat")?
             .to_owned();
 
+        config.reprojection_error = reproj_error;
+
         serde_json::to_writer_pretty(config_file, &config)
             .context("writing updated config file")?;
 
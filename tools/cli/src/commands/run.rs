@@ -0,0 +1,152 @@
+use std::{fs, path::PathBuf, thread, time::Duration};
+
+use anyhow::{Context, Result};
+use clap::{ArgEnum, Parser};
+use stdvis_core::{
+    metrics::{MetricsConfig, PipelineMetrics},
+    pipeline::PipelineReloader,
+};
+use stdvis_opencv::{
+    camera::OcvCamera,
+    overlay::{Annotator, OverlayConfig, OverlayWriter},
+    stages::stage_registry,
+};
+use tracing::{info, warn};
+
+/// A layer of the debug overlay.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    Contours,
+    GroupIds,
+    BoundingBoxes,
+    Corners,
+    Reprojection,
+    Crosshair,
+    Readouts,
+}
+
+#[derive(Debug, Parser)]
+#[clap(about)]
+pub struct Run {
+    /// Stop after processing this many frames
+    #[clap(short = 'n', long)]
+    frames: Option<u64>,
+
+    /// Apply changes to the pipeline definition and its params files between frames
+    #[clap(short, long)]
+    watch: bool,
+
+    /// How often to log frame rate and per-stage timings, in seconds
+    #[clap(long, default_value = "5")]
+    metrics_interval: f64,
+
+    /// Write each frame, annotated with the contours and targets found in it, to this directory
+    #[clap(long, parse(from_os_str))]
+    debug_dir: Option<PathBuf>,
+
+    /// Leave a layer out of the annotated frames
+    #[clap(long, arg_enum, multiple_occurrences = true)]
+    hide: Vec<Layer>,
+
+    /// The path to a pipeline definition, in TOML if it has a .toml extension and JSON otherwise
+    #[clap(parse(from_os_str))]
+    pipeline: PathBuf,
+}
+
+impl Run {
+    pub fn execute(self) -> Result<()> {
+        let mut reloader = PipelineReloader::new(&self.pipeline)?;
+        let registry = stage_registry();
+
+        let definition = reloader.definition();
+        let camera = OcvCamera::new(definition.camera.clone()).context("opening camera")?;
+        let mut pipeline = registry.build(definition, camera)?;
+
+        if definition.outputs.is_empty() {
+            warn!("pipeline has no outputs, so its targets won't be published");
+        }
+
+        let overlay = match self.debug_dir {
+            Some(ref dir) => {
+                fs::create_dir_all(dir).context("creating debug directory")?;
+
+                let mut config = OverlayConfig::default();
+                for layer in &self.hide {
+                    match layer {
+                        Layer::Contours => config.contours = false,
+                        Layer::GroupIds => config.group_ids = false,
+                        Layer::BoundingBoxes => config.bounding_boxes = false,
+                        Layer::Corners => config.corners = false,
+                        Layer::Reprojection => config.reprojection = false,
+                        Layer::Crosshair => config.crosshair = false,
+                        Layer::Readouts => config.readouts = false,
+                    }
+                }
+
+                // Reprojections need the model and method the pipeline solves
+                // with.
+                let annotator = Annotator::for_definition(config.clone(), definition)?;
+                pipeline
+                    .inspectors_mut()
+                    .push(Box::new(OverlayWriter::new(annotator, dir)));
+
+                Some((config, dir, pipeline.inspectors_mut().len() - 1))
+            }
+            None => None,
+        };
+
+        // Allow the camera to "warm up."
+        thread::sleep(Duration::from_millis(1000));
+
+        let mut metrics = PipelineMetrics::new(MetricsConfig {
+            report_interval: self.metrics_interval,
+            ..MetricsConfig::default()
+        });
+
+        let mut count = 0;
+
+        while self.frames.iter().all(|&frames| count < frames) {
+            if self.watch {
+                match reloader.reload(&registry, &mut pipeline) {
+                    Ok(true) => {
+                        if let Some((config, dir, idx)) = &overlay {
+                            match Annotator::for_definition(config.clone(), reloader.definition()) {
+                                Ok(annotator) => {
+                                    pipeline.inspectors_mut()[*idx] =
+                                        Box::new(OverlayWriter::new(annotator, dir));
+                                }
+                                Err(error) => warn!("keeping previous overlay: {error:#}"),
+                            }
+                        }
+                    }
+                    Ok(false) => {}
+                    Err(error) => warn!("keeping previous pipeline: {error:#}"),
+                }
+            }
+
+            count += 1;
+
+            let frame = match pipeline.run_frame() {
+                Ok(frame) => frame,
+                Err(error) => {
+                    metrics.record_error();
+                    warn!("frame failed: {error:#}");
+                    continue;
+                }
+            };
+
+            metrics.record(&frame);
+            if let Some(snapshot) = metrics.report() {
+                info!(
+                    fps = snapshot.fps,
+                    frames = snapshot.frames,
+                    dropped = snapshot.dropped,
+                    errors = snapshot.errors,
+                    "{snapshot}"
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -10,6 +10,7 @@ use stdvis_core::{
     types::{CameraConfig, VisionTarget},
 };
 use stdvis_opencv::{camera::OcvCamera, convert::AsMatView};
+use tracing::info;
 
 #[derive(Default, Serialize, Deserialize)]
 struct Params {
@@ -131,11 +132,14 @@ impl Sample {
             )
             .context("writing image to disk")?;
 
+            let exposure = camera.exposure().unwrap();
+            info!(index, exposure, label = %params.label, "captured sample");
+
             metadata.images.push(ImageMetadata {
                 index,
                 label: params.label.clone(),
                 config: camera_config.clone(),
-                exposure: camera.exposure().unwrap().clone(),
+                exposure,
             });
 
             if let Some(delay) = self.delay() {
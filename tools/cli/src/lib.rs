@@ -1,19 +1,45 @@
-use clap::Parser;
+use clap::{ArgEnum, Parser};
+use tracing_subscriber::EnvFilter;
 
 mod commands;
 
 use self::commands::Commands;
 
+/// The format of diagnostic logs, which are written to stderr.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per event, including the fields of its spans
+    Json,
+}
+
 /// A suite of command-line tools for performing image sampling, calibration, testing, and various other vision-related tasks
 #[derive(Parser)]
 #[clap(version, about)]
 #[clap(propagate_version = true)]
 pub struct Cli {
+    /// The format of diagnostic logs; the level is set by the RUST_LOG environment variable
+    #[clap(long, global = true, arg_enum, default_value = "text")]
+    log_format: LogFormat,
+
     #[clap(subcommand)]
     command: Commands,
 }
 
 impl Cli {
+    /// Installs the global subscriber for diagnostic logs.
+    pub fn init_logging(&self) {
+        let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+        let builder = tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(std::io::stderr);
+
+        match self.log_format {
+            LogFormat::Text => builder.init(),
+            LogFormat::Json => builder.json().with_current_span(true).init(),
+        }
+    }
+
     pub fn command(self) -> Commands {
         self.command
     }
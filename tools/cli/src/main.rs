@@ -4,6 +4,7 @@ use stdvis_cli::Cli;
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    cli.init_logging();
 
     cli.command().execute()?;
     Ok(())